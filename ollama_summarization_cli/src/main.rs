@@ -13,15 +13,156 @@
 // - serde_json: For serializing and deserializing JSON data.
 // - structopt: For parsing command-line arguments.
 // - indicatif: For displaying progress bars.
+// - humantime: For formatting log timestamps.
+// - glob: For matching filename patterns in the params map.
+// - flate2, zstd: For compressed (.gz / .zst) output files.
+// - httpdate: For parsing Retry-After dates.
+// - rand: For the random jitter added to retry backoffs.
+// - sha2: For verifying chunk files against a --checksum-manifest.
+// - dotenvy: For loading flag defaults from an --env-file.
+// - uuid: For the random run ID written with --with-metadata.
+// - zip: For reading chunk files from a .zip archive given as --dir.
+// - transcript-splitter: The transcript splitter's library, for --sub-split.
 //
 // How to Use:
 // 1. Compile the code using the Rust compiler.
 // 2. Run the executable with the following command-line arguments:
-//    -d or --dir: Specifies the directory containing pre-chunked text files.
-//    -o or --output: Specifies the output JSON file.
+//    -d or --dir: Specifies the directory containing pre-chunked text files, or a .zip archive of them, whose
+//                 .txt entries (in any folder of the archive) are read without extracting it, processed in filename
+//                 number order and keyed like --recursive.
+//    -o or --output: Specifies the output JSON file, or "-" to write the results to stdout, with log lines moved
+//                    to stderr.
 //    -u or --url: Specifies the API URL for the Ollama API.
 //    -m or --model: Specifies the model name to use.
+//    --env-file: Loads defaults for the flags above, --params and --api-key from a .env file, which may then
+//                supply the otherwise required --output, --url and --model (optional).
+//    --api-key: Sends this key as an "Authorization: Bearer" header with every request, also read from
+//               SUMMARIZER_API_KEY (optional).
+//    --models: Specifies a comma-separated list of models to compare instead of --model; each file is summarized
+//              by every model and stored as { "filename": { "model": "summary" } } (optional).
 //    --params: Specifies the JSON file containing request parameters (optional).
+//    --params-env: Reads the request parameters as a JSON string from the named environment variable instead of
+//                  a file (optional).
+//    --profile: Selects a named preset from a params file holding several under a top-level "profiles" object
+//               (default "default") (optional).
+//    --validate-json: Checks that the params, request template and params map are valid JSON, reporting the line
+//                     and column of a syntax error, then exits without sending any request (optional).
+//    --request-template: Specifies a JSON file used as the request body instead of the built-in one, with each
+//                        prompt substituted for its "{{text}}" placeholder and the model for "{{model}}", e.g. a
+//                        chat-style "messages" array for OpenAI-compatible endpoints (optional).
+//    --response-pointer: Sets the JSON pointer (RFC 6901) of the summary text in each response, e.g.
+//                        /choices/0/message/content for OpenAI-compatible endpoints (default /response) (optional).
+//    --normalize-unicode: Normalizes each prompt to Unicode NFC or NFKC before it is sent (optional).
+//    --collapse-newlines: Replaces internal newlines in each prompt with spaces (optional).
+//    --sanitize-control: Strips control characters other than newlines and tabs from each chunk before sending (optional).
+//    --context-file: Specifies a text file whose content is prepended to every prompt (optional).
+//    --language: Adds an instruction to summarize in this language (e.g. French) before each chunk (optional).
+//    --language-template: Sets the wording of that instruction, with {language} replaced by the --language value
+//                         (default "Summarize in {language}:").
+//    --rolling-context: Prepends the previous chunk's summary to each prompt for continuity across chunks. Chunks of
+//                       --dir are then processed in filename number order unless --order-from is given (optional).
+//    --use-context: Sends the "context" Ollama returns for each request with the next request, so the model
+//                   remembers the previous chunks. Chunks of --dir are then processed in filename number order
+//                   unless --order-from is given (optional). Not available with --models.
+//    --reduce: After the chunks are summarized, combines their summaries in chunk order into one final summary and
+//              writes it to this text file (optional). Not available with --models.
+//    --reduce-inline: Also combines the chunk summaries into one final summary, stored under the "_combined" key of
+//                     the output alongside the per-chunk entries (optional).
+//    --reduce-prompt: Sets the instruction placed before the summaries in each reduce prompt.
+//    --reduce-fanout: Combines at most this many summaries per reduce request, reducing the results again level by
+//                     level until one summary remains, so the summaries never overflow the context (optional).
+//    --summary-prompt-per-stage: Sets the reduce prompt instruction of one reduce level, given once per level in
+//                                order; the last one is used for deeper levels, and --reduce-prompt is then
+//                                unused (optional, repeatable).
+//    --prompt-suffix: Specifies text appended after each chunk in the prompt (optional).
+//    --log-format: Selects "text" (default) or "json" log output, one JSON object per event (optional).
+//    --trim-incomplete (alias --trim-to-sentence): Cuts each summary after its last complete sentence, dropping a
+//                     trailing fragment left when generation stopped mid-sentence (optional).
+//    --min-summary-ratio: Warns when a summary is shorter than this fraction of its prompt (optional).
+//    --sidecar: Writes each summary next to its chunk file as <chunk>.summary.txt (optional).
+//    --output-key-template: Sets the key of each result in the output from the placeholders {name} (file name),
+//                           {stem}, {ext} (with the leading dot) and {index} (the number at the end of the stem,
+//                           e.g. "001"); defaults to the file name (optional).
+//    --since: Only processes files newer than the existing output JSON, merging into it (optional).
+//    --since-checkpoint: Records each completed file and its result in <output>.checkpoint as it completes, so an
+//                        interrupted run restarted with the flag skips those files; the checkpoint is removed once a
+//                        run completes without failures (optional). Not available with --models.
+//    --output-format: Selects "json" (default), one pretty JSON object written at the end, or "ndjson", one
+//                     { "file", "summary" } object per line appended as each file completes (optional).
+//    --with-metadata: Adds the run ID and start time to the output, as top-level "_run_id" and "_timestamp" keys
+//                     of the JSON object or fields of every NDJSON line (optional).
+//    --run-id: Sets the run ID written with --with-metadata, e.g. to correlate the outputs of a pipeline's tools
+//              (default: a random UUID) (optional).
+//    --params-map: Specifies a JSON file mapping filename patterns (e.g. "*_part_003.txt") to parameter
+//                  overrides merged on top of the global parameters for matching files (optional).
+//    --max-length-ratio: Sets each request's "options.num_predict" to this fraction of the prompt's tokens, overriding the
+//                        params file, e.g. 0.1 for summaries of about 10% of the chunk (optional).
+//    --min-output / --max-output: Clamp the length computed by --max-length-ratio (optional).
+//    --parse-filename-params: Sets "options.num_predict" for chunk files whose name carries a ".len<N>" marker, e.g.
+//                             part_003.len200.txt, overriding the params file, params map and --max-length-ratio
+//                             (optional).
+//    --truncate: Cuts prompts longer than --max-prompt-tokens at the last word boundary within the limit (optional).
+//    --max-prompt-tokens: Sets the token limit used by --truncate and --sub-split, counting whitespace-separated words (optional).
+//    --sub-split: Splits chunks longer than --max-prompt-tokens into sub-chunks with the transcript splitter's logic,
+//                 summarizes each and joins the summaries into the file's entry (optional).
+//    --max-prompt-bytes: Sets the largest prompt, in UTF-8 bytes, that is sent (optional).
+//    --on-oversize: "skip" (default) logs files whose prompt is over --max-prompt-bytes as failed without sending
+//                   them; "truncate" cuts the chunk text at a word boundary so the prompt fits.
+//    --fail-on-error: Sets how many failed files are tolerated before exiting with code 2 (default 0).
+//    --abort-after-failures: Stops the run after this many consecutive failed requests, e.g. when the server went
+//                            down, writing the results collected so far (optional).
+//    --keep-going / --no-keep-going: Whether a chunk file that cannot be read is logged as a failed file and
+//                                    skipped, or aborts the run (default --keep-going).
+//    --manifest: Specifies a file listing the chunk files to process, in order, instead of --dir. Either one path
+//                per line or a JSON array of paths; relative paths are resolved against the manifest's directory (optional).
+//    --recursive: Also processes text files in subdirectories of --dir. Files sharing a name are keyed by their
+//                 path relative to --dir so no summary overwrites another (optional).
+//    --order-from: Processes the chunk files of --dir in the order given by "frontmatter", the "order:" key of a
+//                  leading ---delimited YAML block (which is not sent to the API), or "filename", the number at the
+//                  end of the filename. Files without front-matter fall back to their filename number (optional).
+//    --checksum-manifest: Verifies the SHA-256 of every chunk file against this manifest, as written by the transcript
+//                         splitter's --checksums flag, before sending any request (optional).
+//    --on-checksum-mismatch: "abort" (default) exits without sending anything when a chunk is modified or not listed;
+//                            "skip" leaves such chunks out with a warning and counts them as failed.
+//    --prompt-json-pointer: Also processes .json chunk files, using the string at this JSON pointer (e.g. "/content")
+//                           as the chunk text; .txt files are sent as they are (optional).
+//    --lossy-utf8: Reads chunk files with invalid UTF-8 by replacing the bad bytes with U+FFFD instead of failing
+//                  them (optional).
+//    --no-progress: Disables the progress bar; it is also disabled when stderr is not a terminal (optional).
+//    --proxy: Sends requests through this HTTP, HTTPS or SOCKS5 proxy URL, e.g. socks5://127.0.0.1:1080. Without it
+//             the HTTP_PROXY / HTTPS_PROXY environment variables are honored (optional).
+//    --http2: Speaks HTTP/2 to the API without negotiation (prior knowledge), for servers that support it (optional).
+//    --timeout-per-token: Gives each request a timeout of this many milliseconds per prompt token, but at least
+//                         30 seconds, instead of reqwest's fixed 30 seconds (optional).
+//    --retries: Sets how many times a request failing with a connection error, 429 or 5xx status is retried (default 0).
+//    --retry-backoff: Sets the wait in seconds before the first retry, doubling after each attempt (default 1).
+//                     Each wait is varied randomly by up to 20% so concurrent runs do not retry in lockstep.
+//                     A 429 response's Retry-After header takes precedence over the backoff.
+//    --max-retry-elapsed: Sets the number of seconds after which a failing request is no longer retried, even
+//                         with attempts left (optional).
+//    --retry-on-status: Sets the comma-separated HTTP statuses that are retried, e.g. 429,502,503, instead of 429
+//                       and every 5xx status; connection errors are always retried (optional).
+//    --keep-done-reason: Stores { "response": ..., "done_reason": ... } per file instead of the summary text alone.
+//                        A summary cut off at the token limit (done_reason "length") is always reported (optional).
+//    --shrink-on-overflow: Retries a request rejected for exceeding the model's context with its prompt cut by this
+//                          percentage, up to 3 times, keeping the prompt suffix (optional).
+//    --stream: Streams each chunk's summary as Ollama's newline-delimited JSON ("stream": true), saving the text
+//              generated so far to <chunk>.partial until the summary completes (optional).
+//    --resume-partial: With --stream, continues a summary interrupted in an earlier run from its .partial file,
+//                      sending the prompt, a blank line and the saved text in raw mode, without the model's prompt
+//                      template, for the model to carry on from, and appending the newly generated text (optional).
+//    --dry-run: Prints each file's planned request body without sending it or writing the output (optional).
+//    --count-only: Prints the prompt tokens of each file's planned requests and their total, to estimate the cost of a
+//                  run, without sending anything or writing the output (optional).
+//
+// Identical requests within a run, e.g. from duplicate chunk files, are sent once; every file still gets its entry
+// in the output, mapped to the shared summary.
+//
+// Exit Codes:
+// 0: All files were processed (or no more failures than --fail-on-error allows).
+// 1: Fatal setup error, e.g. an unreadable directory or an unwritable output file.
+// 2: More files failed than --fail-on-error allows, or the run was stopped by --abort-after-failures;
+//    the successful results are still written.
 //
 // Example Usage:
 // $ ./ollama_summarization_cli --dir /path/to/chunked_text_files --output output.json --url http://localhost:11434/api/generate --model phi3
@@ -32,15 +173,101 @@
 //
 // For more information about the Ollama API, visit: [https://github.com/ollama/ollama/blob/main/docs/api.md]
 
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::blocking::Client;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rand::Rng;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, RETRY_AFTER};
+use reqwest::Proxy;
+use reqwest::StatusCode;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     error::Error,
+    fmt,
     fs::{self, File},
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
-use structopt::StructOpt;
+use structopt::{clap, StructOpt};
+use transcript_splitter::{split_tokens, UnicodeForm};
+
+// Suffix of the per-chunk summary files written with --sidecar
+const SIDECAR_SUFFIX: &str = ".summary.txt";
+
+// How long an idle connection is kept for the next request; generation can take minutes,
+// so this outlasts reqwest's 90 second default
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Appended to a chunk file's name to name the partial summary saved while it streams
+const PARTIAL_SUFFIX: &str = ".partial";
+
+// Separates the prompt from the saved partial summary in a --resume-partial continuation
+const CONTINUATION_SEPARATOR: &str = "\n\n";
+
+// Where Ollama responses hold the summary text
+const DEFAULT_RESPONSE_POINTER: &str = "/response";
+
+// Placeholder replaced by each prompt in a --request-template body
+const TEMPLATE_TEXT_PLACEHOLDER: &str = "{{text}}";
+
+// Placeholder replaced by the model in a --request-template body
+const TEMPLATE_MODEL_PLACEHOLDER: &str = "{{model}}";
+
+// Number of times a prompt is cut and sent again with --shrink-on-overflow
+const MAX_SHRINK_ATTEMPTS: u32 = 3;
+
+// Lowercase phrases in an error response that mean the prompt did not fit in the model's context
+const CONTEXT_OVERFLOW_PATTERNS: &[&str] = &[
+    "context length",
+    "context window",
+    "context size",
+    "maximum context",
+    "too long",
+];
+
+// Shortest timeout given to a request under --timeout-per-token, the same as reqwest's default timeout
+const MIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Interval of TCP keep-alive probes on open connections
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+// Fraction by which each retry backoff is randomly lengthened or shortened
+const RETRY_JITTER: f64 = 0.2;
+
+// Introduces the previous chunk's summary in prompts built with --rolling-context
+const ROLLING_CONTEXT_LABEL: &str = "Summary of the previous part:";
+
+// Top-level key of a params file holding named parameter presets, and the preset used without --profile
+const PARAMS_PROFILES_KEY: &str = "profiles";
+const DEFAULT_PARAMS_PROFILE: &str = "default";
+
+// Request body keys the tool sets itself, which a params file or --params-map may not override
+const RESERVED_PARAMS: &[&str] = &["prompt", "model", "stream"];
+
+// Appended to the output path to name the --since-checkpoint file
+const CHECKPOINT_SUFFIX: &str = ".checkpoint";
+
+// --output value writing the results to stdout
+const STDOUT_OUTPUT: &str = "-";
+
+// Top-level output keys of the --with-metadata run ID and start time
+const RUN_ID_KEY: &str = "_run_id";
+const RUN_TIMESTAMP_KEY: &str = "_timestamp";
+// Output key of the combined summary written with --reduce-inline
+const COMBINED_KEY: &str = "_combined";
+
+// Number of prompt characters shown per request in --dry-run output
+const DRY_RUN_PROMPT_CHARS: usize = 200;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Ollama Summarization CLI Tool")]
@@ -48,16 +275,40 @@ struct CliArgs {
     #[structopt(
         short = "d",
         long = "dir",
-        help = "Sets the directory containing text files"
+        required_unless = "manifest",
+        conflicts_with = "manifest",
+        help = "Sets the directory (or .zip archive) containing text files"
     )]
-    dir: String,
+    dir: Option<String>,
 
-    #[structopt(short = "o", long = "output", help = "Sets the output JSON file")]
+    #[structopt(
+        short = "o",
+        long = "output",
+        env = "SUMMARIZER_OUTPUT",
+        required_unless = "env-file",
+        help = "Sets the output JSON file, or - for stdout"
+    )]
     output: String,
 
+    #[structopt(
+        long = "env-file",
+        help = "Loads SUMMARIZER_* defaults for the output, URL, model, params and API key from this .env file (optional)"
+    )]
+    env_file: Option<String>,
+
+    #[structopt(
+        long = "api-key",
+        env = "SUMMARIZER_API_KEY",
+        hide_env_values = true,
+        help = "Sends this key as an \"Authorization: Bearer\" header with every request (optional)"
+    )]
+    api_key: Option<String>,
+
     #[structopt(
         short = "u",
         long = "url",
+        env = "SUMMARIZER_URL",
+        required_unless = "env-file",
         help = "Sets the API URL for the Ollama API"
     )]
     api_url: String,
@@ -65,141 +316,5029 @@ struct CliArgs {
     #[structopt(
         short = "m",
         long = "model",
+        env = "SUMMARIZER_MODEL",
+        required_unless_one = &["models", "env-file"],
+        conflicts_with = "models",
         help = "Sets the model name to use"
     )]
-    model: String,
+    model: Option<String>,
+
+    #[structopt(
+        long = "models",
+        use_delimiter = true,
+        conflicts_with = "sidecar",
+        help = "Sets a comma-separated list of models to run every file through, storing { model: summary } per file"
+    )]
+    models: Vec<String>,
 
     #[structopt(
         short = "p",
         long = "params",
+        env = "SUMMARIZER_PARAMS",
         help = "Sets the JSON file containing request parameters (optional)"
     )]
     params: Option<String>,
-}
 
-fn send_request(
-    api_url: &str,
-    prompt: &str,
-    model: &str,
-    params: Option<&str>,
-) -> Result<String, Box<dyn Error>> {
-    let mut request_body = json!({
-        "model": model,
-        "prompt": prompt.trim(),
-        "stream": false
-    });
+    #[structopt(
+        long = "params-env",
+        value_name = "VARNAME",
+        conflicts_with = "params",
+        help = "Reads the request parameters as a JSON string from this environment variable (optional)"
+    )]
+    params_env: Option<String>,
 
-    if let Some(params_path) = params {
-        let params_json = fs::read_to_string(params_path)?;
-        let params: Value = serde_json::from_str(&params_json)?;
-        if let Value::Object(mut obj) = request_body {
-            merge_json(&mut obj, &params);
-            request_body = Value::Object(obj);
-        }
-    }
+    #[structopt(
+        long = "profile",
+        alias = "params-profile",
+        help = "Selects a named profile from a params file with a top-level \"profiles\" object (default \"default\")"
+    )]
+    profile: Option<String>,
 
-    let client = Client::new();
-    let response = client
-        .post(api_url)
-        .header("accept", "application/json")
-        .header("Content-Type", "application/json")
-        .body(request_body.to_string())
-        .send()?;
+    #[structopt(
+        long = "validate-json",
+        help = "Checks the params, request template and params map JSON, then exits without sending any request"
+    )]
+    validate_json: bool,
 
-    if response.status().is_success() {
-        let response_text = response.text()?;
-        let response_json: Value = serde_json::from_str(&response_text)?;
+    #[structopt(
+        long = "request-template",
+        help = "Sets a JSON file used as the request body, with each prompt substituted for \"{{text}}\" (optional)"
+    )]
+    request_template: Option<String>,
 
-        // Extract and return only the "response" field
-        if let Some(response_value) = response_json.get("response") {
-            if let Some(response_str) = response_value.as_str() {
-                return Ok(response_str.to_string());
-            }
-        }
+    #[structopt(
+        long = "response-pointer",
+        default_value = "/response",
+        help = "Sets the JSON pointer of the summary text in each response, e.g. /choices/0/message/content"
+    )]
+    response_pointer: String,
 
-        // If "response" field is not found, return an error
-        Err("No 'response' field found in JSON".into())
-    } else {
-        // If request fails, return error with status and response text
-        Err(format!("Request failed with status: {}", response.status()).into())
-    }
-}
+    #[structopt(
+        long = "normalize-unicode",
+        possible_values = &["nfc", "nfkc"],
+        help = "Normalizes each prompt to this Unicode form before sending it (optional)"
+    )]
+    normalize_unicode: Option<UnicodeForm>,
 
-fn merge_json(base: &mut serde_json::Map<String, Value>, new: &Value) {
-    if let Value::Object(new_obj) = new {
-        for (key, value) in new_obj.iter() {
-            if let Some(base_value) = base.get_mut(key) {
-                if let Value::Object(base_obj) = base_value {
-                    merge_json(base_obj, value);
-                }
-            } else {
-                base.insert(key.clone(), value.clone());
-            }
-        }
-    }
-}
+    #[structopt(
+        long = "collapse-newlines",
+        help = "Replaces internal newlines in each prompt with spaces instead of preserving them"
+    )]
+    collapse_newlines: bool,
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = CliArgs::from_args();
-    let mut results: HashMap<String, Value> = HashMap::new();
+    #[structopt(
+        long = "sanitize-control",
+        help = "Strips control characters other than newlines and tabs from each chunk before sending it"
+    )]
+    sanitize_control: bool,
 
-    let total_files = fs::read_dir(&args.dir)?
-        .filter_map(|entry| {
-            entry.ok().and_then(|entry| {
-                if let Some(ext) = entry.path().extension() {
-                    if ext == "txt" {
-                        Some(entry.path())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-        })
-        .count();
+    #[structopt(
+        long = "context-file",
+        help = "Sets a text file whose content is prepended to every prompt (optional)"
+    )]
+    context_file: Option<String>,
 
-    let pb = ProgressBar::new(total_files as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%)")?
-            .progress_chars("#>-"),
-    );
+    #[structopt(
+        long = "language",
+        help = "Adds an instruction to summarize in this language before each chunk (optional)"
+    )]
+    language: Option<String>,
 
-    // Iterate over all text files in the directory
-    for entry in fs::read_dir(&args.dir)? {
-        let entry = entry?;
-        let file_path = entry.path();
+    #[structopt(
+        long = "language-template",
+        default_value = "Summarize in {language}:",
+        help = "Sets the wording of the --language instruction; {language} is replaced by the language"
+    )]
+    language_template: String,
 
-        if let Some(ext) = file_path.extension() {
-            if ext != "txt" {
-                // Skip non-text files
-                continue;
-            }
-        } else {
-            // Skip directories and files without extensions
-            continue;
-        }
+    #[structopt(
+        long = "rolling-context",
+        conflicts_with = "models",
+        help = "Prepends the previous chunk's summary to each prompt, processing the chunks in order"
+    )]
+    rolling_context: bool,
 
-        let file_name = file_path.file_name().unwrap().to_string_lossy().to_string();
+    #[structopt(
+        long = "use-context",
+        conflicts_with = "models",
+        help = "Sends the context returned by Ollama for each chunk with the next request, processing the chunks in order"
+    )]
+    use_context: bool,
 
-        // Send request for each file
-        match send_request(&args.api_url, &fs::read_to_string(&file_path)?, &args.model, args.params.as_deref()) {
-            Ok(response) => {
-                // Tag the response with the filename and store in the hashmap
-                results.insert(file_name.clone(), serde_json::Value::String(response));
-            }
-            Err(e) => {
-                println!("Error processing {}: {}", file_name, e);
-            }
-        }
-        pb.inc(1);
-    }
+    #[structopt(
+        long = "reduce",
+        conflicts_with = "models",
+        help = "Combines the chunk summaries into one final summary written to this text file (optional)"
+    )]
+    reduce: Option<String>,
+
+    #[structopt(
+        long = "reduce-inline",
+        conflicts_with = "models",
+        help = "Stores the combined summary under the \"_combined\" key of the output (optional)"
+    )]
+    reduce_inline: bool,
 
-    pb.finish_with_message("All files processed.");
+    #[structopt(
+        long = "reduce-prompt",
+        default_value = "Combine the following partial summaries into a single summary:",
+        help = "Sets the instruction placed before the summaries in each reduce prompt"
+    )]
+    reduce_prompt: String,
+
+    #[structopt(
+        long = "reduce-fanout",
+        help = "Sets how many summaries are combined per reduce request, reducing level by level (optional)"
+    )]
+    reduce_fanout: Option<usize>,
 
-    let mut output_file = File::create(&args.output)?;
-    serde_json::to_writer_pretty(&mut output_file, &results)?;
+    #[structopt(
+        long = "summary-prompt-per-stage",
+        number_of_values = 1,
+        help = "Sets the reduce prompt instruction of the next reduce level, the last repeating for deeper levels, instead of --reduce-prompt (optional, repeatable)"
+    )]
+    summary_prompt_per_stage: Vec<String>,
 
-    Ok(())
-}
\ No newline at end of file
+    #[structopt(
+        long = "prompt-suffix",
+        help = "Sets text appended after each chunk in the prompt, e.g. closing instructions (optional)"
+    )]
+    prompt_suffix: Option<String>,
+
+    #[structopt(
+        long = "log-format",
+        default_value = "text",
+        possible_values = &["text", "json"],
+        help = "Sets the log output format"
+    )]
+    log_format: LogFormat,
+
+    #[structopt(
+        long = "trim-incomplete",
+        alias = "trim-to-sentence",
+        help = "Cuts each summary after its last complete sentence, dropping a trailing partial sentence"
+    )]
+    trim_incomplete: bool,
+
+    #[structopt(
+        long = "min-summary-ratio",
+        help = "Warns when a summary's length relative to its prompt falls below this ratio (optional)"
+    )]
+    min_summary_ratio: Option<f64>,
+
+    #[structopt(
+        long = "sidecar",
+        help = "Also writes each summary next to its chunk file as <chunk>.summary.txt"
+    )]
+    sidecar: bool,
+
+    #[structopt(
+        long = "output-key-template",
+        help = "Sets the output key of each result from {name}, {stem}, {ext} and {index} (optional)"
+    )]
+    output_key_template: Option<String>,
+
+    #[structopt(
+        long = "since",
+        help = "Only processes files modified after the existing output file, keeping its previous results"
+    )]
+    since: bool,
+
+    #[structopt(
+        long = "since-checkpoint",
+        conflicts_with = "models",
+        help = "Records completed files in <output>.checkpoint and skips the files it lists on restart"
+    )]
+    since_checkpoint: bool,
+
+    #[structopt(
+        long = "output-format",
+        default_value = "json",
+        possible_values = &["json", "ndjson"],
+        help = "Sets the output format: one JSON object, or one line per file appended as it completes"
+    )]
+    output_format: OutputFormat,
+
+    #[structopt(
+        long = "with-metadata",
+        help = "Adds the run ID and start time to the output as _run_id and _timestamp"
+    )]
+    with_metadata: bool,
+
+    #[structopt(
+        long = "run-id",
+        requires = "with-metadata",
+        help = "Sets the run ID written with --with-metadata (default: a random UUID)"
+    )]
+    run_id: Option<String>,
+
+    #[structopt(
+        long = "dry-run",
+        help = "Prints the request planned for each file without sending it or writing the output"
+    )]
+    dry_run: bool,
+
+    #[structopt(
+        long = "count-only",
+        conflicts_with = "dry-run",
+        help = "Prints the prompt tokens each file's requests would send and their total, without sending them"
+    )]
+    count_only: bool,
+
+    #[structopt(
+        long = "params-map",
+        help = "Sets a JSON file mapping filename patterns to parameter overrides for matching files (optional)"
+    )]
+    params_map: Option<String>,
+
+    #[structopt(
+        long = "max-length-ratio",
+        help = "Sets the output length of each request to this fraction of the prompt's tokens (optional)"
+    )]
+    max_length_ratio: Option<f64>,
+
+    #[structopt(
+        long = "parse-filename-params",
+        help = "Sets the output length of files named like part_003.len200.txt from their .len<N> marker"
+    )]
+    parse_filename_params: bool,
+
+    #[structopt(
+        long = "min-output",
+        requires = "max-length-ratio",
+        help = "Sets the smallest output length computed by --max-length-ratio (default 1)"
+    )]
+    min_output: Option<usize>,
+
+    #[structopt(
+        long = "max-output",
+        requires = "max-length-ratio",
+        help = "Sets the largest output length computed by --max-length-ratio (optional)"
+    )]
+    max_output: Option<usize>,
+
+    #[structopt(
+        long = "truncate",
+        requires = "max-prompt-tokens",
+        help = "Truncates prompts longer than --max-prompt-tokens at a word boundary instead of sending them whole"
+    )]
+    truncate: bool,
+
+    #[structopt(
+        long = "max-prompt-tokens",
+        help = "Sets the maximum number of whitespace-separated tokens per prompt used by --truncate (optional)"
+    )]
+    max_prompt_tokens: Option<usize>,
+
+    #[structopt(
+        long = "sub-split",
+        requires = "max-prompt-tokens",
+        conflicts_with = "truncate",
+        help = "Splits prompts longer than --max-prompt-tokens into sub-chunks, summarizing each and joining the summaries"
+    )]
+    sub_split: bool,
+
+    #[structopt(
+        long = "max-prompt-bytes",
+        help = "Sets the largest prompt size in bytes; larger prompts are skipped or truncated (optional)"
+    )]
+    max_prompt_bytes: Option<usize>,
+
+    #[structopt(
+        long = "on-oversize",
+        default_value = "skip",
+        possible_values = &["skip", "truncate"],
+        help = "Skips files whose prompt is over --max-prompt-bytes, or truncates their chunk text to fit"
+    )]
+    on_oversize: OversizeAction,
+
+    #[structopt(
+        long = "fail-on-error",
+        default_value = "0",
+        help = "Sets how many failed files are tolerated before exiting with code 2"
+    )]
+    fail_on_error: usize,
+
+    #[structopt(
+        long = "abort-after-failures",
+        help = "Stops the run after this many consecutive failed requests, keeping the results so far (optional)"
+    )]
+    abort_after_failures: Option<usize>,
+
+    #[structopt(
+        long = "keep-going",
+        overrides_with = "no-keep-going",
+        help = "Logs chunk files that cannot be read as failed and processes the rest (default)"
+    )]
+    keep_going: bool,
+
+    #[structopt(
+        long = "no-keep-going",
+        overrides_with = "keep-going",
+        help = "Aborts the run when a chunk file cannot be read"
+    )]
+    no_keep_going: bool,
+
+    #[structopt(
+        long = "manifest",
+        help = "Sets a file listing the chunk files to process in order, instead of scanning --dir"
+    )]
+    manifest: Option<String>,
+
+    #[structopt(
+        long = "recursive",
+        conflicts_with = "manifest",
+        help = "Also processes text files in subdirectories of --dir"
+    )]
+    recursive: bool,
+
+    #[structopt(
+        long = "order-from",
+        possible_values = &["filename", "frontmatter"],
+        conflicts_with = "manifest",
+        help = "Processes chunk files in the order of their front-matter order key or filename number (optional)"
+    )]
+    order_from: Option<OrderSource>,
+
+    #[structopt(
+        long = "checksum-manifest",
+        help = "Verifies chunk files against this SHA-256 manifest from the splitter's --checksums (optional)"
+    )]
+    checksum_manifest: Option<String>,
+
+    #[structopt(
+        long = "on-checksum-mismatch",
+        default_value = "abort",
+        possible_values = &["abort", "skip"],
+        help = "Aborts, or skips the file with a warning, when a chunk does not match the checksum manifest"
+    )]
+    on_checksum_mismatch: ChecksumMismatch,
+
+    #[structopt(
+        long = "prompt-json-pointer",
+        help = "Also processes .json chunk files, sending the string at this JSON pointer (e.g. /content) as the prompt"
+    )]
+    prompt_json_pointer: Option<String>,
+
+    #[structopt(
+        long = "lossy-utf8",
+        help = "Replaces invalid UTF-8 in chunk files with U+FFFD instead of failing them"
+    )]
+    lossy_utf8: bool,
+
+    #[structopt(
+        long = "no-progress",
+        help = "Disables the progress bar, which is also disabled when stderr is not a terminal"
+    )]
+    no_progress: bool,
+
+    #[structopt(
+        long = "keep-done-reason",
+        help = "Stores each result as { \"response\", \"done_reason\" } instead of the summary text alone"
+    )]
+    keep_done_reason: bool,
+
+    #[structopt(
+        long = "proxy",
+        help = "Sets an HTTP, HTTPS or SOCKS5 proxy URL for requests instead of HTTP_PROXY / HTTPS_PROXY (optional)"
+    )]
+    proxy: Option<String>,
+
+    #[structopt(
+        long = "http2",
+        help = "Uses HTTP/2 with prior knowledge instead of HTTP/1.1 for requests"
+    )]
+    http2: bool,
+
+    #[structopt(
+        long = "timeout-per-token",
+        help = "Sets each request's timeout to this many milliseconds per prompt token, at least 30 seconds (optional)"
+    )]
+    timeout_per_token: Option<u64>,
+
+    #[structopt(
+        long = "retries",
+        default_value = "0",
+        help = "Sets how many times a failed request is retried"
+    )]
+    retries: u32,
+
+    #[structopt(
+        long = "retry-backoff",
+        default_value = "1",
+        help = "Sets the wait in seconds before the first retry, doubling after each attempt"
+    )]
+    retry_backoff: f64,
+
+    #[structopt(
+        long = "max-retry-elapsed",
+        help = "Sets the number of seconds after which a failing request is no longer retried (optional)"
+    )]
+    max_retry_elapsed: Option<f64>,
+
+    #[structopt(
+        long = "retry-on-status",
+        use_delimiter = true,
+        help = "Sets the comma-separated HTTP statuses that are retried (default 429 and 5xx)"
+    )]
+    retry_on_status: Vec<u16>,
+
+    #[structopt(
+        long = "shrink-on-overflow",
+        value_name = "percent",
+        help = "Retries requests rejected for exceeding the context with the prompt cut by this percentage (optional)"
+    )]
+    shrink_on_overflow: Option<f64>,
+
+    #[structopt(
+        long = "stream",
+        conflicts_with_all = &["request-template", "models"],
+        help = "Streams each summary as newline-delimited JSON, saving the text so far to <chunk>.partial"
+    )]
+    stream: bool,
+
+    #[structopt(
+        long = "resume-partial",
+        requires = "stream",
+        help = "Continues an interrupted summary from its <chunk>.partial file instead of starting over"
+    )]
+    resume_partial: bool,
+}
+
+impl CliArgs {
+    // Whether unreadable chunk files are skipped rather than aborting the run. Keeping going is the default;
+    // of --keep-going and --no-keep-going, the one given last wins.
+    fn keep_going(&self) -> bool {
+        self.keep_going || !self.no_keep_going
+    }
+
+    // Whether the chunk summaries are combined into one, with --reduce or --reduce-inline
+    fn reduce(&self) -> bool {
+        self.reduce.is_some() || self.reduce_inline
+    }
+}
+
+// Parse the command line. An --env-file is loaded into the environment and the command line parsed again, so
+// its SUMMARIZER_* variables supply defaults for the flags that read them. Flags given on the command line and
+// variables already set in the environment take precedence.
+fn parse_args(args: &[String]) -> Result<CliArgs, clap::Error> {
+    let mut matches = CliArgs::clap().get_matches_from_safe(args)?;
+    if let Some(env_file) = matches.value_of("env-file").map(String::from) {
+        dotenvy::from_path(&env_file).map_err(|e| {
+            clap::Error::with_description(
+                &format!("Could not load env file {}: {}", env_file, e),
+                clap::ErrorKind::Io,
+            )
+        })?;
+        matches = CliArgs::clap().get_matches_from_safe(args)?;
+    }
+    // Flags required unless an env file is given must then come from the file
+    let missing: Vec<&str> = [("output", "--output"), ("api-url", "--url")]
+        .into_iter()
+        .filter(|(name, _)| !matches.is_present(name))
+        .map(|(_, flag)| flag)
+        .chain((!matches.is_present("model") && !matches.is_present("models")).then_some("--model"))
+        .collect();
+    if !missing.is_empty() {
+        return Err(clap::Error::with_description(
+            &format!(
+                "The following required arguments were not provided: {}",
+                missing.join(", ")
+            ),
+            clap::ErrorKind::MissingRequiredArgument,
+        ));
+    }
+    Ok(CliArgs::from_clap(&matches))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format: {}", s)),
+        }
+    }
+}
+
+// Where the processing order of the chunk files is read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OrderSource {
+    Filename,
+    Frontmatter,
+}
+
+impl FromStr for OrderSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "filename" => Ok(OrderSource::Filename),
+            "frontmatter" => Ok(OrderSource::Frontmatter),
+            _ => Err(format!("Invalid order source: {}", s)),
+        }
+    }
+}
+
+// What to do with chunk files that fail checksum verification
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChecksumMismatch {
+    Abort,
+    Skip,
+}
+
+impl FromStr for ChecksumMismatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ChecksumMismatch::Abort),
+            "skip" => Ok(ChecksumMismatch::Skip),
+            _ => Err(format!("Invalid checksum mismatch action: {}", s)),
+        }
+    }
+}
+
+// What to do with prompts over --max-prompt-bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OversizeAction {
+    Skip,
+    Truncate,
+}
+
+impl FromStr for OversizeAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OversizeAction::Skip),
+            "truncate" => Ok(OversizeAction::Truncate),
+            _ => Err(format!("Invalid oversize action: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Json,
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+// A shared handle to an output stream, so the log and the results can write to the same one
+#[derive(Clone)]
+struct OutputStream(Rc<RefCell<dyn Write>>);
+
+impl Write for OutputStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+// Where a run prints its output: the process's stdout and stderr, or buffers in the tests
+#[derive(Clone)]
+struct Console {
+    stdout: OutputStream,
+    stderr: OutputStream,
+}
+
+impl Console {
+    fn process() -> Self {
+        Console {
+            stdout: OutputStream(Rc::new(RefCell::new(std::io::stdout()))),
+            stderr: OutputStream(Rc::new(RefCell::new(std::io::stderr()))),
+        }
+    }
+
+    // Like println!, panicking if the line cannot be written
+    fn println(&self, line: &str) {
+        writeln!(self.stdout.clone(), "{}", line).expect("failed printing to stdout");
+    }
+
+    // Like eprintln!, panicking if the line cannot be written
+    fn eprintln(&self, line: &str) {
+        writeln!(self.stderr.clone(), "{}", line).expect("failed printing to stderr");
+    }
+}
+
+// Logs processing events; text mode only reports errors and warnings,
+// json mode emits one object per event
+struct EventLog {
+    format: LogFormat,
+    // Whether log lines go to stderr, keeping stdout free for the results
+    to_stderr: bool,
+    console: Console,
+}
+
+impl EventLog {
+    fn started(&self, file_name: &str) {
+        self.emit("file_started", file_name, "ok", None, None);
+    }
+
+    fn completed(&self, file_name: &str, duration: Duration) {
+        self.emit("file_completed", file_name, "ok", Some(duration), None);
+    }
+
+    fn failed(&self, file_name: &str, duration: Duration, error: &str) {
+        self.emit(
+            "file_failed",
+            file_name,
+            "error",
+            Some(duration),
+            Some(error),
+        );
+    }
+
+    fn warning(&self, event: &str, file_name: &str, message: &str) {
+        self.emit(event, file_name, "warning", None, Some(message));
+    }
+
+    // A failed request about to be sent again: `attempt` counts the retries of the request, starting at 1.
+    // Like the start and completion of a file, retries are only logged as json.
+    fn retry(&self, file_name: &str, attempt: u32, delay: Duration) {
+        if self.format == LogFormat::Json {
+            let mut record = Self::record("retry", file_name, "retry");
+            record["attempt"] = json!(attempt);
+            record["delay_ms"] = json!(delay.as_millis() as u64);
+            self.write(&record.to_string());
+        }
+    }
+
+    fn emit(
+        &self,
+        event: &str,
+        file_name: &str,
+        status: &str,
+        duration: Option<Duration>,
+        message: Option<&str>,
+    ) {
+        let line = match self.format {
+            LogFormat::Text => match (status, message) {
+                ("error", Some(message)) => format!("Error processing {}: {}", file_name, message),
+                ("warning", Some(message)) => format!("Warning for {}: {}", file_name, message),
+                _ => return,
+            },
+            LogFormat::Json => {
+                let mut record = Self::record(event, file_name, status);
+                if let Some(duration) = duration {
+                    record["duration_ms"] = json!(duration.as_millis() as u64);
+                }
+                if let Some(message) = message {
+                    record["message"] = json!(message);
+                }
+                record.to_string()
+            }
+        };
+        self.write(&line);
+    }
+
+    // The fields every json log line starts with
+    fn record(event: &str, file_name: &str, status: &str) -> Value {
+        json!({
+            "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            "event": event,
+            "filename": file_name,
+            "status": status,
+        })
+    }
+
+    fn write(&self, line: &str) {
+        if self.to_stderr {
+            self.console.eprintln(line);
+        } else {
+            self.console.println(line);
+        }
+    }
+}
+
+// Ratio of summary length to prompt length, in characters
+fn summary_ratio(prompt: &str, summary: &str) -> f64 {
+    let prompt_len = prompt.chars().count();
+    if prompt_len == 0 {
+        return 1.0;
+    }
+    summary.trim().chars().count() as f64 / prompt_len as f64
+}
+
+// Options controlling how chunk text is turned into a prompt
+#[derive(Default)]
+struct PromptOptions {
+    normalize_unicode: Option<UnicodeForm>,
+    collapse_newlines: bool,
+    sanitize_control: bool,
+    context: Option<String>,
+    language_instruction: Option<String>,
+    suffix: Option<String>,
+    max_tokens: Option<usize>,
+    sub_split: bool,
+    // Byte limit each prompt is truncated to
+    max_bytes: Option<usize>,
+}
+
+// Returns one prompt, or one per sub-chunk when an oversized chunk is sub-split,
+// along with whether the chunk was truncated
+fn prepare_prompts(
+    text: &str,
+    options: &PromptOptions,
+    previous_summary: Option<&str>,
+) -> (Vec<String>, bool) {
+    let rolling_context =
+        previous_summary.map(|summary| format!("{}\n{}", ROLLING_CONTEXT_LABEL, summary.trim()));
+
+    // Normalize first, so token budgets count the text as it is sent
+    let normalized;
+    let text = match options.normalize_unicode {
+        Some(form) => {
+            normalized = form.apply(text);
+            normalized.as_str()
+        }
+        None => text,
+    };
+
+    let sanitized;
+    let text = if options.sanitize_control {
+        sanitized = strip_control_chars(text);
+        sanitized.trim()
+    } else {
+        text.trim()
+    };
+    let text = if options.collapse_newlines {
+        // Join non-empty lines with single spaces
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    } else {
+        text.to_string()
+    };
+
+    // Leave room for the context and suffix within the token limit
+    let mut truncated = false;
+    let pieces = match options.max_tokens {
+        Some(max_tokens) => {
+            let reserved = [
+                &options.context,
+                &rolling_context,
+                &options.language_instruction,
+                &options.suffix,
+            ]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .map(|part| part.split_whitespace().count())
+            .sum::<usize>();
+            let budget = max_tokens.saturating_sub(reserved);
+            if options.sub_split {
+                if text.split_whitespace().count() > budget {
+                    split_tokens(&text, budget.max(1)).expect("the budget is at least one token")
+                } else {
+                    vec![text]
+                }
+            } else {
+                match truncate_at_word_boundary(&text, budget) {
+                    Some(kept) => {
+                        truncated = true;
+                        vec![kept.to_string()]
+                    }
+                    None => vec![text],
+                }
+            }
+        }
+        None => vec![text],
+    };
+
+    let assemble = |text: &str| {
+        let text = match &options.suffix {
+            Some(suffix) => format!("{}\n\n{}", text, suffix),
+            None => text.to_string(),
+        };
+        let text = match &options.language_instruction {
+            Some(instruction) => format!("{}\n\n{}", instruction, text),
+            None => text,
+        };
+        let text = match &rolling_context {
+            Some(rolling_context) => format!("{}\n\n{}", rolling_context, text),
+            None => text,
+        };
+        match &options.context {
+            Some(context) => format!("{}\n\n{}", context, text),
+            None => text,
+        }
+    };
+
+    let prompts = pieces
+        .into_iter()
+        .map(|text| {
+            let prompt = assemble(&text);
+            match options.max_bytes {
+                // Cut only the chunk text, keeping the context and suffix
+                Some(max_bytes) if prompt.len() > max_bytes => {
+                    truncated = true;
+                    let budget = max_bytes.saturating_sub(prompt.len() - text.len());
+                    assemble(truncate_to_bytes(&text, budget))
+                }
+                _ => prompt,
+            }
+        })
+        .collect();
+    (prompts, truncated)
+}
+
+// Output length for a prompt: ratio times its whitespace-separated tokens, rounded and clamped
+// to [min, max]; the minimum defaults to 1 so short prompts still get an answer
+fn output_length(prompt: &str, ratio: f64, min: Option<usize>, max: Option<usize>) -> usize {
+    let length = (ratio * prompt.split_whitespace().count() as f64).round() as usize;
+    let length = length.max(min.unwrap_or(1));
+    max.map_or(length, |max| length.min(max))
+}
+
+// Cut text after its last sentence-ending punctuation (and any closing quotes or brackets), dropping the
+// partial sentence left when generation stops at the token limit; text without a complete sentence is kept
+fn trim_incomplete_sentence(text: &str) -> &str {
+    match text.rfind(['.', '!', '?']) {
+        Some(end) => {
+            let rest = &text[end + 1..];
+            let closing = rest.len() - rest.trim_start_matches(['"', '\'', ')', ']']).len();
+            &text[..end + 1 + closing]
+        }
+        None => text,
+    }
+}
+
+// Remove control characters, such as NUL or form feeds from PDF extraction, that some backends reject
+// in the request, keeping the line breaks and tabs that structure the text
+fn strip_control_chars(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .collect()
+}
+
+// Cut text before its first whitespace-separated token beyond max_tokens,
+// or None when it already fits
+fn truncate_at_word_boundary(text: &str, max_tokens: usize) -> Option<&str> {
+    let mut tokens = 0;
+    let mut in_token = false;
+    for (index, c) in text.char_indices() {
+        if c.is_whitespace() {
+            in_token = false;
+        } else if !in_token {
+            in_token = true;
+            tokens += 1;
+            if tokens > max_tokens {
+                return Some(text[..index].trim_end());
+            }
+        }
+    }
+    None
+}
+
+// Cut text to at most `max_bytes` bytes at the last word boundary within the limit
+fn truncate_to_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let kept = &text[..end];
+    // Drop the word cut in the middle, unless the text has no earlier word boundary
+    let kept = if text[end..].starts_with(char::is_whitespace) {
+        kept
+    } else {
+        kept.rfind(char::is_whitespace)
+            .map_or(kept, |boundary| &kept[..boundary])
+    };
+    kept.trim_end()
+}
+
+// Whether --dir names a .zip archive of chunk files rather than a directory
+fn is_zip_archive(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+// The chunk entries of a .zip archive given as --dir, read into memory and keyed by their paths in the archive
+type ZipEntries = HashMap<PathBuf, Vec<u8>>;
+
+// Read the .txt entries of a .zip archive (and .json entries with `include_json`), in any folder of the archive,
+// skipping summary sidecar files written by --sidecar
+fn read_zip_archive(path: &Path, include_json: bool) -> Result<ZipEntries, Box<dyn Error>> {
+    let zip_error =
+        |e: zip::result::ZipError| format!("Could not read zip archive {}: {}", path.display(), e);
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(zip_error)?;
+    let mut entries = HashMap::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(zip_error)?;
+        if entry.is_dir() {
+            continue;
+        }
+        // Entry names like "../x.txt" or "/x.txt" would not be keyed by a path inside the archive
+        let entry_path = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| {
+                format!(
+                    "Zip archive {} has an entry outside the archive: {}",
+                    path.display(),
+                    entry.name()
+                )
+            })?;
+        let extension = entry_path.extension();
+        let is_chunk = extension == Some("txt".as_ref())
+            || (include_json && extension == Some("json".as_ref()));
+        if !is_chunk || entry_path.to_string_lossy().ends_with(SIDECAR_SUFFIX) {
+            continue;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        entries.insert(entry_path, content);
+    }
+    Ok(entries)
+}
+
+// List the .txt chunk files in a directory, skipping summary sidecar files written by --sidecar.
+// With `keep_going`, entries and subdirectories that cannot be read are skipped with a warning.
+fn collect_chunk_files(
+    dir: &Path,
+    recursive: bool,
+    include_json: bool,
+    keep_going: bool,
+    log: &EventLog,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut chunk_files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let file_path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) if keep_going => {
+                log.warning(
+                    "entry_skipped",
+                    &dir.display().to_string(),
+                    &format!("skipping an unreadable entry: {}", e),
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        if recursive && file_path.is_dir() {
+            match collect_chunk_files(&file_path, recursive, include_json, keep_going, log) {
+                Ok(files) => chunk_files.extend(files),
+                Err(e) if keep_going => log.warning(
+                    "directory_skipped",
+                    &file_path.display().to_string(),
+                    &format!("skipping the directory: {}", e),
+                ),
+                Err(e) => return Err(e),
+            }
+            continue;
+        }
+
+        let extension = file_path.extension();
+        let is_chunk = extension == Some("txt".as_ref())
+            || (include_json && extension == Some("json".as_ref()));
+        if !is_chunk {
+            // Skip directories, non-text files and files without extensions
+            continue;
+        }
+        if file_path.to_string_lossy().ends_with(SIDECAR_SUFFIX) {
+            continue;
+        }
+        chunk_files.push(file_path);
+    }
+    Ok(chunk_files)
+}
+
+// Key each file's result by its name, qualifying names shared by several files with their path
+// relative to the base directory so no summary overwrites another. With a key template the keys are
+// rendered from it instead, and must be unique.
+fn result_keys(
+    chunk_files: &[PathBuf],
+    base_dir: &Path,
+    key_template: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if let Some(template) = key_template {
+        let mut keys: Vec<String> = Vec::with_capacity(chunk_files.len());
+        for (position, path) in chunk_files.iter().enumerate() {
+            let key = render_key_template(template, path, position + 1);
+            if keys.contains(&key) {
+                return Err(format!(
+                    "Output key template '{}' gives the key '{}' to more than one chunk file",
+                    template, key
+                )
+                .into());
+            }
+            keys.push(key);
+        }
+        return Ok(keys);
+    }
+
+    let names: Vec<String> = chunk_files
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for name in &names {
+        *name_counts.entry(name).or_insert(0) += 1;
+    }
+
+    let mut keys = Vec::with_capacity(names.len());
+    for (path, name) in chunk_files.iter().zip(&names) {
+        let key = if name_counts[name.as_str()] > 1 {
+            let relative = path.strip_prefix(base_dir).unwrap_or(path);
+            relative.to_string_lossy().replace('\\', "/")
+        } else {
+            name.clone()
+        };
+        // Only the same file listed twice can still collide
+        if keys.contains(&key) {
+            return Err(format!("Chunk file {} is listed more than once", path.display()).into());
+        }
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+// Render an output key template for a chunk file. {index} is the number at the end of the file stem as
+// written (e.g. "001" for x_part_001.txt), ignoring a ".len<N>" marker, or the file's position in processing
+// order when it has none.
+fn render_key_template(template: &str, path: &Path, position: usize) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let numbered = strip_length_marker(&stem);
+    let digits_start = numbered
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    let index = match &numbered[digits_start..] {
+        "" => format!("{:03}", position),
+        digits => digits.to_string(),
+    };
+    template
+        .replace("{name}", &name)
+        .replace("{stem}", &stem)
+        .replace("{ext}", &ext)
+        .replace("{index}", &index)
+}
+
+// Read the chunk files listed in a manifest: a JSON array of paths, or one path per line
+// (blank lines and lines starting with '#' are ignored)
+fn read_manifest(manifest: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let content = read_text_file(manifest)?;
+    let entries: Vec<String> = if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content)?
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    };
+
+    // Relative paths are listed relative to the manifest itself
+    let base_dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    Ok(entries.iter().map(|entry| base_dir.join(entry)).collect())
+}
+
+// The chunk text of a file: for .json files with a JSON pointer, the string at that pointer
+fn chunk_text(
+    file_path: &Path,
+    content: String,
+    json_pointer: Option<&str>,
+) -> Result<String, String> {
+    match json_pointer {
+        Some(pointer) if file_path.extension() == Some("json".as_ref()) => {
+            let chunk: Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+            chunk
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .map(String::from)
+                .ok_or_else(|| format!("no string found at JSON pointer {}", pointer))
+        }
+        _ => Ok(content),
+    }
+}
+
+// Sort chunk files by their front-matter order key, falling back to the number at the end of the filename,
+// then by path
+fn sort_chunk_files(
+    chunk_files: &mut [PathBuf],
+    order_from: OrderSource,
+    zip_entries: Option<&ZipEntries>,
+    lossy_utf8: bool,
+) -> std::io::Result<()> {
+    let mut keys = HashMap::new();
+    for file_path in chunk_files.iter() {
+        let order = match order_from {
+            OrderSource::Frontmatter => {
+                front_matter_order(&read_chunk_file(file_path, zip_entries, lossy_utf8)?)
+            }
+            OrderSource::Filename => None,
+        };
+        keys.insert(
+            file_path.clone(),
+            order.unwrap_or_else(|| filename_number(file_path)),
+        );
+    }
+    chunk_files.sort_by(|a, b| keys[a].cmp(&keys[b]).then_with(|| a.cmp(b)));
+    Ok(())
+}
+
+// A file stem without its trailing ".len<N>" length marker, e.g. part_003 for part_003.len200
+fn strip_length_marker(stem: &str) -> &str {
+    match stem.rsplit_once(".len") {
+        Some((base, digits))
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => stem,
+    }
+}
+
+// The output length given by a ".len<N>" marker in a chunk's file name, e.g. 200 for part_003.len200.txt
+fn filename_max_length(file_path: &Path) -> Option<u64> {
+    let stem = file_path.file_stem()?.to_str()?;
+    let base = strip_length_marker(stem);
+    stem[base.len()..].strip_prefix(".len")?.parse().ok()
+}
+
+// The number at the end of a file's stem, ignoring a ".len<N>" marker, e.g. 3 for transcript_part_003.txt, or 0
+// without one
+fn filename_number(file_path: &Path) -> i64 {
+    let stem = file_path.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = strip_length_marker(&stem);
+    let digits_start = stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    stem[digits_start..].parse().unwrap_or(0)
+}
+
+// Split a leading ---delimited front-matter block from the text, returning the block's lines and the rest
+fn split_front_matter(text: &str) -> Option<(&str, &str)> {
+    let rest = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+// The integer "order:" key of a chunk's front-matter
+fn front_matter_order(text: &str) -> Option<i64> {
+    let (front_matter, _) = split_front_matter(text)?;
+    front_matter
+        .lines()
+        .find_map(|line| line.strip_prefix("order:"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+// Read a checksum manifest of "<sha256>  <file>" lines, as written by sha256sum or the splitter's --checksums,
+// keyed by the files' paths resolved against the manifest's directory
+fn read_checksum_manifest(manifest: &Path) -> Result<HashMap<PathBuf, String>, Box<dyn Error>> {
+    let base_dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let mut checksums = HashMap::new();
+    for line in read_text_file(manifest)?.lines() {
+        // Comment lines, such as the splitter's --with-metadata run ID, are skipped like sha256sum does
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (checksum, file) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("Invalid checksum manifest line: {}", line))?;
+        // sha256sum marks files hashed in binary mode with a leading '*'
+        let file = file.trim_start().trim_start_matches('*');
+        checksums.insert(
+            checksum_key(&base_dir.join(file)),
+            checksum.to_ascii_lowercase(),
+        );
+    }
+    Ok(checksums)
+}
+
+// Path under which a file is looked up in the checksum manifest, so ./dir/a.txt and dir/a.txt match
+fn checksum_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Check a chunk file's SHA-256 against the checksum manifest
+fn verify_checksum(file_path: &Path, checksums: &HashMap<PathBuf, String>) -> Result<(), String> {
+    let expected = checksums
+        .get(&checksum_key(file_path))
+        .ok_or("not listed in the checksum manifest")?;
+    let content = fs::read(file_path).map_err(|e| e.to_string())?;
+    let actual = format!("{:x}", Sha256::digest(&content));
+    if &actual != expected {
+        return Err(format!(
+            "SHA-256 {} does not match the checksum manifest ({})",
+            actual, expected
+        ));
+    }
+    Ok(())
+}
+
+// Read a text file, dropping a leading UTF-8 byte order mark
+fn read_text_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let text = fs::read_to_string(path)?;
+    match text.strip_prefix('\u{feff}') {
+        Some(stripped) => Ok(stripped.to_string()),
+        None => Ok(text),
+    }
+}
+
+// Read a chunk file, or the entry at `path` of the zip archive being processed; with `lossy`, invalid UTF-8 is
+// replaced with U+FFFD instead of failing the read
+fn read_chunk_file(
+    path: &Path,
+    zip_entries: Option<&ZipEntries>,
+    lossy: bool,
+) -> std::io::Result<String> {
+    let bytes = match zip_entries {
+        Some(entries) => entries
+            .get(path)
+            .cloned()
+            .ok_or(std::io::ErrorKind::NotFound)?,
+        None if !lossy => return read_text_file(path),
+        None => fs::read(path)?,
+    };
+    let text = if lossy {
+        String::from_utf8_lossy(&bytes).into_owned()
+    } else {
+        String::from_utf8(bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+    };
+    Ok(text.strip_prefix('\u{feff}').unwrap_or(&text).to_string())
+}
+
+// Compression of the output file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl OutputCompression {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => OutputCompression::Gzip,
+            Some("zst") => OutputCompression::Zstd,
+            _ => OutputCompression::None,
+        }
+    }
+}
+
+// Read the results of a previous run, decompressing .gz and .zst outputs
+fn read_output(path: &Path) -> Result<HashMap<String, Value>, Box<dyn Error>> {
+    let content = match OutputCompression::from_path(path) {
+        OutputCompression::None => read_text_file(path)?,
+        OutputCompression::Gzip => {
+            let mut content = String::new();
+            GzDecoder::new(File::open(path)?).read_to_string(&mut content)?;
+            content
+        }
+        OutputCompression::Zstd => {
+            let mut content = String::new();
+            zstd::Decoder::new(File::open(path)?)?.read_to_string(&mut content)?;
+            content
+        }
+    };
+    Ok(serde_json::from_str(&content)?)
+}
+
+// Write the results as pretty JSON, compressed according to the output extension, or to stdout for "-"
+fn write_output(
+    path: &Path,
+    results: &HashMap<String, Value>,
+    console: &Console,
+) -> Result<(), Box<dyn Error>> {
+    if path == Path::new(STDOUT_OUTPUT) {
+        let mut stdout = console.stdout.clone();
+        serde_json::to_writer_pretty(&mut stdout, results)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+    let output_file = File::create(path)?;
+    match OutputCompression::from_path(path) {
+        OutputCompression::None => serde_json::to_writer_pretty(output_file, results)?,
+        OutputCompression::Gzip => {
+            let mut encoder = GzEncoder::new(output_file, Compression::default());
+            serde_json::to_writer_pretty(&mut encoder, results)?;
+            encoder.finish()?;
+        }
+        OutputCompression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output_file, 0)?;
+            serde_json::to_writer_pretty(&mut encoder, results)?;
+            encoder.finish()?;
+        }
+    }
+    Ok(())
+}
+
+// Open the NDJSON output, appending to it in incremental mode and starting it over otherwise
+fn open_ndjson_output(path: &Path, append: bool) -> std::io::Result<File> {
+    if OutputCompression::from_path(path) != OutputCompression::None {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "NDJSON output cannot be compressed",
+        ));
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+}
+
+// The --with-metadata fields identifying this run: the given run ID, or a random UUID, and the current time
+fn run_metadata(run_id: Option<&str>) -> serde_json::Map<String, Value> {
+    let run_id = run_id.map_or_else(|| uuid::Uuid::new_v4().to_string(), String::from);
+    let mut metadata = serde_json::Map::new();
+    metadata.insert(RUN_ID_KEY.to_string(), json!(run_id));
+    metadata.insert(
+        RUN_TIMESTAMP_KEY.to_string(),
+        json!(humantime::format_rfc3339_seconds(SystemTime::now()).to_string()),
+    );
+    metadata
+}
+
+// Append one file's summary to the NDJSON output as a single line
+fn write_ndjson_line(
+    output: &mut dyn Write,
+    file_name: &str,
+    model: Option<&str>,
+    summary: &str,
+    metadata: Option<&serde_json::Map<String, Value>>,
+) -> std::io::Result<()> {
+    let mut line = json!({ "file": file_name, "summary": summary });
+    if let Some(model) = model {
+        line["model"] = json!(model);
+    }
+    if let (Some(metadata), Value::Object(fields)) = (metadata, &mut line) {
+        fields.extend(metadata.clone());
+    }
+    writeln!(output, "{}", line)?;
+    output.flush()
+}
+
+// Checkpoint file written next to the output with --since-checkpoint
+fn checkpoint_path(output: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", output, CHECKPOINT_SUFFIX))
+}
+
+// Read the files completed by a previous run from its checkpoint, one {"key", "result"} JSON line each.
+// A line cut short by an interruption is ignored.
+fn read_checkpoint(path: &Path) -> std::io::Result<Vec<(String, Value)>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let entries = read_text_file(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|entry| {
+            let key = entry.get("key")?.as_str()?.to_string();
+            Some((key, entry.get("result")?.clone()))
+        })
+        .collect();
+    Ok(entries)
+}
+
+// Record a completed file in the checkpoint, syncing it to disk so it survives a crash
+fn write_checkpoint_line(checkpoint: &mut File, key: &str, result: &Value) -> std::io::Result<()> {
+    writeln!(checkpoint, "{}", json!({ "key": key, "result": result }))?;
+    checkpoint.sync_data()
+}
+
+// Create the output's parent directory and check that the output file can be opened for writing,
+// without leaving an empty file behind
+fn ensure_output_writable(output: &Path) -> std::io::Result<()> {
+    if let Some(parent) = output
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let existed = output.exists();
+    fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(output)?;
+    if !existed {
+        fs::remove_file(output)?;
+    }
+    Ok(())
+}
+
+// Path of the summary sidecar written next to a chunk file
+fn sidecar_path(file_path: &Path) -> PathBuf {
+    let stem = file_path.file_stem().unwrap().to_string_lossy();
+    file_path.with_file_name(format!("{}{}", stem, SIDECAR_SUFFIX))
+}
+
+// Where the request parameters come from: a JSON file (--params) or a JSON string in an environment
+// variable (--params-env)
+enum ParamsSource {
+    File(String),
+    Env(String),
+}
+
+impl ParamsSource {
+    // Read the parameters' JSON text
+    fn read(&self) -> Result<String, Box<dyn Error>> {
+        match self {
+            ParamsSource::File(path) => Ok(read_text_file(path)?),
+            ParamsSource::Env(name) => std::env::var(name).map_err(|e| {
+                format!(
+                    "Could not read params from environment variable {}: {}",
+                    name, e
+                )
+                .into()
+            }),
+        }
+    }
+
+    // Name the source in error messages
+    fn describe(&self) -> String {
+        match self {
+            ParamsSource::File(path) => format!("params file {}", path),
+            ParamsSource::Env(name) => format!("params variable {}", name),
+        }
+    }
+
+    // Read and parse the parameters, selecting `profile` from them; done once per run
+    fn load(&self, profile: Option<&str>) -> Result<Value, Box<dyn Error>> {
+        let params = parse_json(&self.read()?, &self.describe())?;
+        let params = select_params_profile(params, profile)?;
+        check_reserved_params(&params, &self.describe())?;
+        Ok(params)
+    }
+}
+
+// Parse JSON read from `source`, naming it and the line and column of a syntax error
+fn parse_json(text: &str, source: &str) -> Result<Value, String> {
+    serde_json::from_str(text).map_err(|e| format!("Invalid JSON in {}: {}", source, e))
+}
+
+// The params source selected on the command line, if any
+fn params_source(args: &CliArgs) -> Option<ParamsSource> {
+    match (&args.params, &args.params_env) {
+        (Some(path), _) => Some(ParamsSource::File(path.clone())),
+        (None, Some(name)) => Some(ParamsSource::Env(name.clone())),
+        (None, None) => None,
+    }
+}
+
+// Pick the parameters of a params file: a file with a top-level "profiles" object holds named presets, of which
+// `profile` (or the "default" profile) is used; any other file is used as it is
+fn select_params_profile(params: Value, profile: Option<&str>) -> Result<Value, String> {
+    let Some(profiles) = params.get(PARAMS_PROFILES_KEY) else {
+        return match profile {
+            Some(profile) => Err(format!(
+                "Params profile '{}' requested, but the params file has no \"{}\" object",
+                profile, PARAMS_PROFILES_KEY
+            )),
+            None => Ok(params),
+        };
+    };
+    let profile = profile.unwrap_or(DEFAULT_PARAMS_PROFILE);
+    profiles
+        .get(profile)
+        .cloned()
+        .ok_or_else(|| format!("Params profile '{}' not found in the params file", profile))
+}
+
+// Read a --request-template body: a JSON object with the {{text}} placeholder in at least one string
+fn read_request_template(path: &str) -> Result<Value, Box<dyn Error>> {
+    let template = parse_json(
+        &read_text_file(path)?,
+        &format!("request template {}", path),
+    )?;
+    if !template.is_object() {
+        return Err(format!("Request template {} is not a JSON object", path).into());
+    }
+    if !template.to_string().contains(TEMPLATE_TEXT_PLACEHOLDER) {
+        return Err(format!(
+            "Request template {} has no {} placeholder",
+            path, TEMPLATE_TEXT_PLACEHOLDER
+        )
+        .into());
+    }
+    Ok(template)
+}
+
+// Copy a template, replacing the placeholders in its strings at any depth
+fn fill_template(template: &Value, replacements: &[(&str, &str)]) -> Value {
+    match template {
+        Value::String(text) => Value::String(
+            replacements
+                .iter()
+                .fold(text.clone(), |text, (placeholder, value)| {
+                    text.replace(placeholder, value)
+                }),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| fill_template(item, replacements))
+                .collect(),
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), fill_template(value, replacements)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+// Build the request body for a prompt
+fn build_request_body(
+    prompt: &str,
+    model: &str,
+    template: Option<&Value>,
+    params: Option<&Value>,
+) -> Result<Value, Box<dyn Error>> {
+    let mut request_body = match template {
+        // The model is filled in first, so a prompt containing "{{model}}" is sent as it is
+        Some(template) => fill_template(
+            template,
+            &[
+                (TEMPLATE_MODEL_PLACEHOLDER, model),
+                (TEMPLATE_TEXT_PLACEHOLDER, prompt),
+            ],
+        ),
+        None => json!({
+            "model": model,
+            "prompt": prompt,
+            "stream": false
+        }),
+    };
+
+    if let Some(params) = params {
+        if let Value::Object(mut obj) = request_body {
+            merge_json(&mut obj, params);
+            request_body = Value::Object(obj);
+        }
+    }
+
+    Ok(request_body)
+}
+
+// A response with an unsuccessful status, keeping what the retry logic needs
+#[derive(Debug)]
+struct StatusError {
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    // Whether the response body says the prompt exceeded the model's context
+    context_overflow: bool,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Request failed with status: {}", self.status)?;
+        if self.context_overflow {
+            write!(f, " (prompt exceeds the context length)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for StatusError {}
+
+impl StatusError {
+    fn from_response(response: Response) -> Self {
+        let status = response.status();
+        // Only rate limiting responses ask for a specific delay
+        let retry_after = if status == StatusCode::TOO_MANY_REQUESTS {
+            response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after)
+        } else {
+            None
+        };
+        let body = response.text().unwrap_or_default().to_lowercase();
+        StatusError {
+            status,
+            retry_after,
+            context_overflow: CONTEXT_OVERFLOW_PATTERNS
+                .iter()
+                .any(|pattern| body.contains(pattern)),
+        }
+    }
+}
+
+// Parse a Retry-After value, given either as seconds or as an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+// Build the HTTP client shared by all requests, keeping connections alive so consecutive files reuse them.
+// An explicit proxy applies to every request; otherwise reqwest picks up the HTTP_PROXY / HTTPS_PROXY
+// environment variables.
+fn build_client(
+    proxy: Option<&str>,
+    http2: bool,
+    api_key: Option<&str>,
+) -> Result<Client, Box<dyn Error>> {
+    let mut builder = Client::builder()
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE);
+    if let Some(api_key) = api_key {
+        let mut authorization = HeaderValue::from_str(&format!("Bearer {}", api_key))?;
+        authorization.set_sensitive(true);
+        builder = builder.default_headers(HeaderMap::from_iter([(AUTHORIZATION, authorization)]));
+    }
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    if http2 {
+        builder = builder.http2_prior_knowledge();
+    }
+    Ok(builder.build()?)
+}
+
+// Retry settings for failed requests
+struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+    max_elapsed: Option<Duration>,
+    // Statuses retried instead of 429 and 5xx, from --retry-on-status
+    statuses: Option<HashSet<u16>>,
+}
+
+// Key identifying a request within a run, so identical requests are only sent once
+fn request_key(request_body: &Value) -> String {
+    format!("{:x}", Sha256::digest(request_body.to_string()))
+}
+
+// Send a request, retrying connection errors and 429 and 5xx responses (or those of policy.statuses) up to
+// policy.retries times.
+// A 429 response's Retry-After delay is honored; otherwise the wait doubles after each attempt, with up to
+// RETRY_JITTER of random variation. Retrying stops early once the next wait would exceed policy.max_elapsed.
+fn send_with_retries<T>(
+    policy: &RetryPolicy,
+    log: &EventLog,
+    file_name: &str,
+    mut send: impl FnMut() -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let started = Instant::now();
+    let mut backoff = policy.backoff;
+    let mut attempt = 0;
+    loop {
+        match send() {
+            Ok(value) => return Ok(value),
+            Err(e)
+                if attempt < policy.retries
+                    && is_retryable(e.as_ref(), policy.statuses.as_ref()) =>
+            {
+                let wait = match e.downcast_ref::<StatusError>() {
+                    Some(StatusError {
+                        retry_after: Some(delay),
+                        ..
+                    }) => *delay,
+                    _ => backoff.mul_f64(
+                        rand::thread_rng().gen_range(1.0 - RETRY_JITTER..=1.0 + RETRY_JITTER),
+                    ),
+                };
+                if let Some(max_elapsed) = policy.max_elapsed {
+                    if started.elapsed() + wait > max_elapsed {
+                        return Err(e);
+                    }
+                }
+                attempt += 1;
+                log.retry(file_name, attempt, wait);
+                thread::sleep(wait);
+                backoff *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Whether a failed request may succeed when sent again: a connection error, or a status in `statuses` if given
+// and otherwise a 429 or 5xx status
+fn is_retryable(error: &(dyn Error + 'static), statuses: Option<&HashSet<u16>>) -> bool {
+    match error.downcast_ref::<StatusError>() {
+        Some(e) => match statuses {
+            Some(statuses) => statuses.contains(&e.status.as_u16()),
+            None => e.status == StatusCode::TOO_MANY_REQUESTS || e.status.is_server_error(),
+        },
+        None => error.is::<reqwest::Error>(),
+    }
+}
+
+// A generated summary along with why generation stopped ("stop", or "length" when cut off at the token limit)
+#[derive(Clone)]
+struct Generation {
+    response: String,
+    done_reason: Option<String>,
+    // The conversation context returned by Ollama, continued by the next request with --use-context
+    context: Option<Value>,
+}
+
+impl Generation {
+    // Join the generations of a sub-split chunk, reporting "length" if any part was cut off
+    fn combine(generations: Vec<Generation>) -> Generation {
+        let done_reason = if generations.iter().any(Generation::hit_length_limit) {
+            Some("length".to_string())
+        } else {
+            generations
+                .last()
+                .and_then(|generation| generation.done_reason.clone())
+        };
+        let context = generations
+            .last()
+            .and_then(|generation| generation.context.clone());
+        let responses: Vec<String> = generations
+            .into_iter()
+            .map(|generation| generation.response)
+            .collect();
+        Generation {
+            response: responses.join("\n\n"),
+            done_reason,
+            context,
+        }
+    }
+
+    fn hit_length_limit(&self) -> bool {
+        self.done_reason.as_deref() == Some("length")
+    }
+
+    // The stored result: the summary text, or the summary with its done_reason
+    fn into_result(self, keep_done_reason: bool) -> Value {
+        if keep_done_reason {
+            json!({ "response": self.response, "done_reason": self.done_reason })
+        } else {
+            Value::String(self.response)
+        }
+    }
+}
+
+// Timeout of a request under --timeout-per-token, proportional to the tokens of its prompt but never below
+// MIN_REQUEST_TIMEOUT; None keeps the client's default timeout
+fn request_timeout(timeout_per_token: Option<u64>, request_body: &Value) -> Option<Duration> {
+    let per_token_ms = timeout_per_token?;
+    let tokens = prompt_tokens(request_body);
+    let timeout = Duration::from_millis(per_token_ms.saturating_mul(tokens as u64));
+    Some(timeout.max(MIN_REQUEST_TIMEOUT))
+}
+
+// Whitespace-separated tokens of a request's prompt: its "prompt" string, or all strings of a
+// --request-template body, which hold the prompt wherever the template puts it
+fn prompt_tokens(request_body: &Value) -> usize {
+    match request_body.get("prompt").and_then(Value::as_str) {
+        Some(prompt) => prompt.split_whitespace().count(),
+        None => body_tokens(request_body),
+    }
+}
+
+// Whitespace-separated tokens in the strings of a value
+fn body_tokens(value: &Value) -> usize {
+    match value {
+        Value::String(text) => text.split_whitespace().count(),
+        Value::Array(items) => items.iter().map(body_tokens).sum(),
+        Value::Object(fields) => fields.values().map(body_tokens).sum(),
+        _ => 0,
+    }
+}
+
+fn send_request(
+    client: &Client,
+    api_url: &str,
+    request_body: &Value,
+    timeout: Option<Duration>,
+    response_pointer: &str,
+) -> Result<Generation, Box<dyn Error>> {
+    let mut request = client
+        .post(api_url)
+        .header("accept", "application/json")
+        .header("Content-Type", "application/json")
+        .body(request_body.to_string());
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+    let response = request.send()?;
+
+    if response.status().is_success() {
+        let response_text = response.text()?;
+        let response_json: Value = serde_json::from_str(&response_text)?;
+
+        // Extract and return only the text at the response pointer ("/response" by default), along with the
+        // reason generation stopped
+        if let Some(response_str) = response_json
+            .pointer(response_pointer)
+            .and_then(Value::as_str)
+        {
+            return Ok(Generation {
+                response: response_str.to_string(),
+                done_reason: response_json
+                    .get("done_reason")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                context: response_json.get("context").cloned(),
+            });
+        }
+
+        // If the text is not found, return an error
+        Err(format!("No text found at {} in the response", response_pointer).into())
+    } else {
+        // If request fails, return error with status and response text
+        Err(Box::new(StatusError::from_response(response)))
+    }
+}
+
+// Send a request; with --shrink-on-overflow (`shrink_percent`), a request rejected for exceeding the model's
+// context is sent again with its prompt cut by that percentage, up to MAX_SHRINK_ATTEMPTS times
+fn send_shrinking<T>(
+    request_body: &Value,
+    shrink_percent: Option<f64>,
+    suffix: Option<&str>,
+    log: &EventLog,
+    label: &str,
+    mut send: impl FnMut(&Value) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let mut request_body = request_body.clone();
+    let mut shrinks = 0;
+    loop {
+        let error = match send(&request_body) {
+            Err(e) if shrinks < MAX_SHRINK_ATTEMPTS && is_context_overflow(e.as_ref()) => e,
+            result => return result,
+        };
+        let shrunk = shrink_percent.and_then(|percent| {
+            let prompt = request_body.get("prompt")?.as_str()?;
+            shrink_prompt(prompt, percent / 100.0, suffix)
+        });
+        let Some(shrunk) = shrunk else {
+            return Err(error);
+        };
+        log.warning(
+            "prompt_shrunk",
+            label,
+            &format!(
+                "Prompt exceeds the context length; retrying with {} tokens",
+                shrunk.split_whitespace().count()
+            ),
+        );
+        request_body["prompt"] = json!(shrunk);
+        shrinks += 1;
+    }
+}
+
+fn is_context_overflow(error: &(dyn std::error::Error + 'static)) -> bool {
+    error
+        .downcast_ref::<StatusError>()
+        .is_some_and(|e| e.context_overflow)
+}
+
+// Cut the last `fraction` of a prompt's words, keeping a trailing prompt suffix whole, or None when no words
+// would be left
+fn shrink_prompt(prompt: &str, fraction: f64, suffix: Option<&str>) -> Option<String> {
+    let (text, suffix) = match suffix.and_then(|suffix| prompt.strip_suffix(suffix)) {
+        Some(text) => (text, &prompt[text.len()..]),
+        None => (prompt, ""),
+    };
+    let words = text.split_whitespace().count();
+    let keep = (words as f64 * (1.0 - fraction)).floor() as usize;
+    if keep == 0 {
+        return None;
+    }
+    let kept = truncate_at_word_boundary(text, keep)?;
+    if suffix.is_empty() {
+        Some(kept.to_string())
+    } else {
+        Some(format!("{}\n\n{}", kept, suffix))
+    }
+}
+
+// Path of the partial summary saved next to a chunk file while it streams; each part of a sub-split chunk
+// gets its own
+fn partial_path(file_path: &Path, part: usize, parts: usize) -> PathBuf {
+    let name = file_path.file_name().unwrap().to_string_lossy();
+    if parts > 1 {
+        file_path.with_file_name(format!("{}.part{}{}", name, part + 1, PARTIAL_SUFFIX))
+    } else {
+        file_path.with_file_name(format!("{}{}", name, PARTIAL_SUFFIX))
+    }
+}
+
+// The prompt of a resumed stream: the original prompt, then the saved partial summary after a separator, as the
+// start of the answer for the model to continue
+fn continuation_prompt(prompt: &str, partial: &str) -> String {
+    format!("{}{}{}", prompt, CONTINUATION_SEPARATOR, partial)
+}
+
+// Send a streaming request, reading the summary from Ollama's newline-delimited JSON objects as it is generated.
+// The text generated so far is kept in `partial` and removed once the final object arrives, so an interrupted
+// summary survives the run. With `resume`, the text of an earlier interrupted stream is sent after the prompt
+// in raw mode, so Ollama does not wrap it in the model's template as a new question but lets the model continue
+// the saved text, and the new text is appended to it.
+fn send_stream_request(
+    client: &Client,
+    api_url: &str,
+    request_body: &Value,
+    timeout: Option<Duration>,
+    partial: &Path,
+    resume: bool,
+) -> Result<Generation, Box<dyn Error>> {
+    let mut text = if resume && partial.exists() {
+        read_text_file(partial)?
+    } else {
+        String::new()
+    };
+    let mut request_body = request_body.clone();
+    request_body["stream"] = json!(true);
+    if !text.is_empty() {
+        let prompt = request_body["prompt"].as_str().unwrap_or_default();
+        request_body["prompt"] = json!(continuation_prompt(prompt, &text));
+        request_body["raw"] = json!(true);
+    }
+
+    let mut request = client
+        .post(api_url)
+        .header("accept", "application/x-ndjson")
+        .header("Content-Type", "application/json")
+        .body(request_body.to_string());
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+    let response = request.send()?;
+    if !response.status().is_success() {
+        return Err(Box::new(StatusError::from_response(response)));
+    }
+
+    let mut partial_file = File::create(partial)?;
+    partial_file.write_all(text.as_bytes())?;
+    let interrupted = |text: &str, reason: &dyn fmt::Display| {
+        format!(
+            "Stream interrupted, {} characters kept in {}: {}",
+            text.chars().count(),
+            partial.display(),
+            reason
+        )
+    };
+    for line in BufReader::new(response).lines() {
+        let line = line.map_err(|e| interrupted(&text, &e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object: Value = serde_json::from_str(&line)?;
+        if let Some(error) = object.get("error").and_then(Value::as_str) {
+            return Err(interrupted(&text, &error).into());
+        }
+        if let Some(token) = object.get("response").and_then(Value::as_str) {
+            text.push_str(token);
+            partial_file.write_all(token.as_bytes())?;
+            partial_file.flush()?;
+        }
+        // The final object carries why generation stopped and the conversation context
+        if object.get("done").and_then(Value::as_bool) == Some(true) {
+            drop(partial_file);
+            fs::remove_file(partial)?;
+            return Ok(Generation {
+                response: text,
+                done_reason: object
+                    .get("done_reason")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+                context: object.get("context").cloned(),
+            });
+        }
+    }
+    Err(interrupted(&text, &"the stream ended before generation was done").into())
+}
+
+// Label of a sub-chunk's request in dry-run output
+fn part_label(file_name: &str, index: usize, parts: usize) -> String {
+    if parts > 1 {
+        format!("{} (part {}/{})", file_name, index + 1, parts)
+    } else {
+        file_name.to_string()
+    }
+}
+
+// Whether to draw the progress bar. It would interleave with json log lines and dry-run output, and fills
+// redirected logs with carriage returns, so it is only drawn for text logs on a terminal.
+fn draws_progress(args: &CliArgs, stderr_is_terminal: bool) -> bool {
+    args.log_format == LogFormat::Text && !args.dry_run && !args.no_progress && stderr_is_terminal
+}
+
+// Print the request that would be sent for a file, shortening the prompt for readability
+fn print_planned_request(console: &Console, file_name: &str, request_body: &Value) {
+    let mut shown = request_body.clone();
+    if let Some(prompt) = request_body.get("prompt").and_then(Value::as_str) {
+        if prompt.chars().count() > DRY_RUN_PROMPT_CHARS {
+            let shortened: String = prompt.chars().take(DRY_RUN_PROMPT_CHARS).collect();
+            shown["prompt"] = json!(format!("{}...", shortened));
+        }
+    }
+    console.println(&format!(
+        "{}:\n{}",
+        file_name,
+        serde_json::to_string_pretty(&shown).unwrap_or_default()
+    ));
+}
+
+// Read a params map file: a JSON object mapping filename glob patterns to parameter overrides
+fn read_params_map(path: &str) -> Result<Vec<(Pattern, Value)>, Box<dyn Error>> {
+    let Value::Object(map) = parse_json(&read_text_file(path)?, &format!("params map {}", path))?
+    else {
+        return Err(format!("Params map {} is not a JSON object", path).into());
+    };
+    map.into_iter()
+        .map(|(pattern, overrides)| {
+            check_reserved_params(
+                &overrides,
+                &format!("params map {} pattern {}", path, pattern),
+            )?;
+            Ok((Pattern::new(&pattern)?, overrides))
+        })
+        .collect()
+}
+
+// Reject parameters that would replace a key of the request body the tool sets itself, such as the prompt
+fn check_reserved_params(params: &Value, source: &str) -> Result<(), String> {
+    match RESERVED_PARAMS
+        .iter()
+        .find(|key| params.get(**key).is_some())
+    {
+        Some(key) => Err(format!(
+            "{} sets \"{}\", which the tool sets itself and cannot be overridden",
+            source, key
+        )),
+        None => Ok(()),
+    }
+}
+
+// Merge the overrides of every params map pattern matching the file name into the request body
+fn apply_params_map(request_body: &mut Value, file_name: &str, params_map: &[(Pattern, Value)]) {
+    if let Value::Object(obj) = request_body {
+        for (pattern, overrides) in params_map {
+            if pattern.matches(file_name) {
+                merge_json(obj, overrides);
+            }
+        }
+    }
+}
+
+fn merge_json(base: &mut serde_json::Map<String, Value>, new: &Value) {
+    if let Value::Object(new_obj) = new {
+        for (key, value) in new_obj.iter() {
+            match (base.get_mut(key), value) {
+                // Nested objects are merged key by key
+                (Some(Value::Object(base_obj)), Value::Object(_)) => merge_json(base_obj, value),
+                // Any other value overrides the existing one
+                _ => {
+                    base.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+// The summary text of a stored result: the text, or the response written with --keep-done-reason
+fn stored_summary(result: &Value) -> Option<&str> {
+    result
+        .as_str()
+        .or_else(|| result.get("response").and_then(Value::as_str))
+}
+
+// The instruction of a reduce prompt at `level`, starting at 1: the level's --summary-prompt-per-stage prompt,
+// the last one for deeper levels, or else --reduce-prompt
+fn stage_reduce_prompt(args: &CliArgs, level: usize) -> &str {
+    let stages = &args.summary_prompt_per_stage;
+    stages
+        .get(level - 1)
+        .or(stages.last())
+        .unwrap_or(&args.reduce_prompt)
+}
+
+// Combine summaries into one, `fanout` at a time: each group of a level is summarized by `summarize`
+// (given the level, starting at 1, and the group's summaries), and the results are reduced again until
+// a single summary remains. A group of one summary is passed on unchanged.
+fn reduce_summaries(
+    mut summaries: Vec<String>,
+    fanout: usize,
+    mut summarize: impl FnMut(usize, &[String]) -> Result<String, Box<dyn Error>>,
+) -> Result<String, Box<dyn Error>> {
+    if summaries.is_empty() {
+        return Err("no summaries to reduce".into());
+    }
+    let mut level = 1;
+    while summaries.len() > 1 {
+        summaries = summaries
+            .chunks(fanout)
+            .map(|group| match group {
+                [summary] => Ok(summary.clone()),
+                _ => summarize(level, group),
+            })
+            .collect::<Result<_, _>>()?;
+        level += 1;
+    }
+    Ok(summaries.remove(0))
+}
+
+// The options applied to every prompt, with the --context-file read once
+fn prompt_options(args: &CliArgs) -> Result<PromptOptions, Box<dyn Error>> {
+    // Read the shared context once; it is prepended to every prompt
+    let context = match &args.context_file {
+        Some(path) => Some(read_text_file(path)?.trim().to_string()),
+        None => None,
+    };
+    // The text added around each chunk is normalized like the chunks themselves
+    let normalize = |text: String| match args.normalize_unicode {
+        Some(form) => form.apply(&text),
+        None => text,
+    };
+    Ok(PromptOptions {
+        normalize_unicode: args.normalize_unicode,
+        collapse_newlines: args.collapse_newlines,
+        sanitize_control: args.sanitize_control,
+        context: context.map(normalize),
+        language_instruction: args
+            .language
+            .as_ref()
+            .map(|language| normalize(args.language_template.replace("{language}", language))),
+        suffix: args.prompt_suffix.clone().map(normalize),
+        max_tokens: if args.truncate || args.sub_split {
+            args.max_prompt_tokens
+        } else {
+            None
+        },
+        sub_split: args.sub_split,
+        max_bytes: args
+            .max_prompt_bytes
+            .filter(|_| args.on_oversize == OversizeAction::Truncate),
+    })
+}
+
+// Reject option values and combinations that cannot work, before any file is read or request sent
+fn check_args(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    if args
+        .shrink_on_overflow
+        .is_some_and(|percent| !(percent > 0.0 && percent < 100.0))
+    {
+        return Err("--shrink-on-overflow must be a percentage between 0 and 100".into());
+    }
+    if args.reduce_fanout.is_some_and(|fanout| fanout < 2) {
+        return Err("--reduce-fanout must be at least 2".into());
+    }
+    if args.abort_after_failures == Some(0) {
+        return Err("--abort-after-failures must be at least 1".into());
+    }
+    if args.reduce_fanout.is_some() && !args.reduce() {
+        return Err("--reduce-fanout requires --reduce or --reduce-inline".into());
+    }
+    if !args.summary_prompt_per_stage.is_empty() && !args.reduce() {
+        return Err("--summary-prompt-per-stage requires --reduce or --reduce-inline".into());
+    }
+    if args.output == STDOUT_OUTPUT && (args.since || args.since_checkpoint) {
+        return Err("--since and --since-checkpoint need an output file, not stdout".into());
+    }
+    if args.profile.is_some() && params_source(args).is_none() {
+        return Err("--profile requires --params or --params-env".into());
+    }
+    // A JSON pointer is empty or a sequence of "/"-prefixed reference tokens
+    if !args.response_pointer.is_empty() && !args.response_pointer.starts_with('/') {
+        return Err(format!(
+            "--response-pointer {} is not a JSON pointer; it must start with '/'",
+            args.response_pointer
+        )
+        .into());
+    }
+    // Streamed summaries are read from Ollama's own objects, not from a JSON response
+    if args.stream && args.response_pointer != DEFAULT_RESPONSE_POINTER {
+        return Err("--stream cannot be used with --response-pointer".into());
+    }
+    Ok(())
+}
+
+// The chunk files of a run, in processing order
+struct Chunks {
+    files: Vec<PathBuf>,
+    // Directory the result keys of the files are relative to
+    base_dir: PathBuf,
+    // The entries of a .zip archive given as --dir, read into memory
+    zip_entries: Option<ZipEntries>,
+}
+
+// Collect the chunk files of the --manifest or --dir and put them in processing order. A .zip archive's entries
+// are read into memory and keyed by their paths in the archive, wherever they are in it.
+fn collect_chunks(args: &CliArgs, log: &EventLog) -> Result<Chunks, Box<dyn Error>> {
+    let mut zip_entries = None;
+    let (mut files, base_dir) = match &args.manifest {
+        Some(manifest) => {
+            let manifest = Path::new(manifest);
+            let base_dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+            (read_manifest(manifest)?, base_dir.to_path_buf())
+        }
+        None => {
+            let dir = args
+                .dir
+                .as_deref()
+                .expect("--dir is required without --manifest");
+            let dir = PathBuf::from(dir);
+            if is_zip_archive(&dir) {
+                if args.since {
+                    return Err("--since cannot be used with a zip archive".into());
+                }
+                if args.sidecar {
+                    return Err("--sidecar cannot be used with a zip archive".into());
+                }
+                if args.stream {
+                    return Err("--stream cannot be used with a zip archive".into());
+                }
+                if args.checksum_manifest.is_some() {
+                    return Err("--checksum-manifest cannot be used with a zip archive".into());
+                }
+                let entries =
+                    zip_entries.insert(read_zip_archive(&dir, args.prompt_json_pointer.is_some())?);
+                (entries.keys().cloned().collect(), PathBuf::new())
+            } else {
+                (
+                    collect_chunk_files(
+                        &dir,
+                        args.recursive,
+                        args.prompt_json_pointer.is_some(),
+                        args.keep_going(),
+                        log,
+                    )?,
+                    dir,
+                )
+            }
+        }
+    };
+    // Rolling context, the Ollama context and the reduce only make sense in chunk order, so directory order is not
+    // used for them, nor for a zip archive's entries
+    let order_from = match args.order_from {
+        None if (args.rolling_context
+            || args.use_context
+            || args.reduce()
+            || zip_entries.is_some())
+            && args.manifest.is_none() =>
+        {
+            Some(OrderSource::Filename)
+        }
+        order_from => order_from,
+    };
+    if let Some(order_from) = order_from {
+        sort_chunk_files(
+            &mut files,
+            order_from,
+            zip_entries.as_ref(),
+            args.lossy_utf8,
+        )?;
+    }
+    Ok(Chunks {
+        files,
+        base_dir,
+        zip_entries,
+    })
+}
+
+// Verify every chunk file against the --checksum-manifest before sending anything, so a corrupted chunk aborts
+// the run up front or, with --on-checksum-mismatch skip, is dropped from it. Returns the number of files dropped.
+fn verify_checksums(
+    args: &CliArgs,
+    chunk_files: &mut Vec<PathBuf>,
+    log: &EventLog,
+) -> Result<usize, Box<dyn Error>> {
+    let Some(manifest) = &args.checksum_manifest else {
+        return Ok(0);
+    };
+    let checksums = read_checksum_manifest(Path::new(manifest))?;
+    let mut mismatches = Vec::new();
+    chunk_files.retain(|file_path| match verify_checksum(file_path, &checksums) {
+        Ok(()) => true,
+        Err(e) => {
+            mismatches.push((file_path.display().to_string(), e.to_string()));
+            false
+        }
+    });
+    let abort = args.on_checksum_mismatch == ChecksumMismatch::Abort;
+    for (file_name, error) in &mismatches {
+        if abort {
+            log.failed(file_name, Duration::ZERO, error);
+        } else {
+            log.warning(
+                "checksum_mismatch",
+                file_name,
+                &format!("skipping the chunk: {}", error),
+            );
+        }
+    }
+    if abort && !mismatches.is_empty() {
+        return Err(format!(
+            "Checksum verification failed for {} chunk files",
+            mismatches.len()
+        )
+        .into());
+    }
+    Ok(mismatches.len())
+}
+
+// Restore the results of the files completed before an interrupted run from the checkpoint at `path`, returning
+// their keys and the checkpoint opened to append the files completed next
+fn resume_checkpoint(
+    path: &Path,
+    results: &mut HashMap<String, Value>,
+) -> Result<(HashSet<String>, File), Box<dyn Error>> {
+    let mut completed = HashSet::new();
+    for (key, result) in read_checkpoint(path)? {
+        completed.insert(key.clone());
+        results.insert(key, result);
+    }
+    let checkpoint = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| {
+            format!(
+                "Checkpoint file {} could not be opened: {}",
+                path.display(),
+                e
+            )
+        })?;
+    Ok((completed, checkpoint))
+}
+
+// The progress bar of a run over `length` requests; a hidden bar still counts them
+fn progress_bar(length: u64, draw: bool) -> Result<ProgressBar, Box<dyn Error>> {
+    let pb = ProgressBar::with_draw_target(
+        Some(length),
+        if draw {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        },
+    );
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%)")?
+            .progress_chars("#>-"),
+    );
+    Ok(pb)
+}
+
+// The prompts of a chunk file's content: one, or one per part with --sub-split. A chunk that cannot be sent, such as
+// one over --max-prompt-bytes with --on-oversize skip, gives the reason the file failed instead.
+fn chunk_prompts(
+    args: &CliArgs,
+    file_path: &Path,
+    file_name: &str,
+    mut content: String,
+    prompt_options: &PromptOptions,
+    previous_summary: Option<&str>,
+    log: &EventLog,
+) -> Result<Vec<String>, String> {
+    // The front-matter only carries the chunk's metadata, so it is not part of the prompt
+    if args.order_from == Some(OrderSource::Frontmatter) {
+        if let Some((_, body)) = split_front_matter(&content) {
+            content = body.to_string();
+        }
+    }
+    let text = chunk_text(file_path, content, args.prompt_json_pointer.as_deref())?;
+    let (prompts, truncated) = prepare_prompts(&text, prompt_options, previous_summary);
+    if truncated {
+        log.warning(
+            "prompt_truncated",
+            file_name,
+            &format!(
+                "Prompt truncated to {} tokens ({} bytes)",
+                prompts[0].split_whitespace().count(),
+                prompts[0].len()
+            ),
+        );
+    }
+    if let Some(max_bytes) = args
+        .max_prompt_bytes
+        .filter(|_| args.on_oversize == OversizeAction::Skip)
+    {
+        if let Some(size) = prompts
+            .iter()
+            .map(String::len)
+            .find(|&size| size > max_bytes)
+        {
+            return Err(format!(
+                "prompt is {} bytes, over the --max-prompt-bytes limit of {}; skipped",
+                size, max_bytes
+            ));
+        }
+    }
+    Ok(prompts)
+}
+
+// What the request bodies of a run are built from
+struct RequestSettings {
+    // The --request-template body, or None for the built-in one
+    template: Option<Value>,
+    params: Option<Value>,
+    // Per-file parameter overrides from --params-map
+    params_map: Vec<(Pattern, Value)>,
+}
+
+impl RequestSettings {
+    // Read the params, --request-template and --params-map. They are parsed once up front, so a typo fails the
+    // run before any request rather than every file.
+    fn load(args: &CliArgs) -> Result<RequestSettings, Box<dyn Error>> {
+        let params = match params_source(args) {
+            Some(source) => Some(source.load(args.profile.as_deref())?),
+            None => None,
+        };
+        let template = match &args.request_template {
+            Some(path) => Some(read_request_template(path)?),
+            None => None,
+        };
+        let params_map = match &args.params_map {
+            Some(path) => read_params_map(path)?,
+            None => Vec::new(),
+        };
+        Ok(RequestSettings {
+            template,
+            params,
+            params_map,
+        })
+    }
+
+    // The request body of each of a chunk file's prompts for `model`
+    fn request_bodies(
+        &self,
+        args: &CliArgs,
+        model: &str,
+        file_path: &Path,
+        file_name: &str,
+        prompts: &[String],
+    ) -> Result<Vec<Value>, Box<dyn Error>> {
+        prompts
+            .iter()
+            .map(|prompt| {
+                let mut request_body = self.request_body(prompt, model)?;
+                apply_params_map(&mut request_body, file_name, &self.params_map);
+                if let Some(ratio) = args.max_length_ratio {
+                    if !request_body["options"].is_object() {
+                        request_body["options"] = json!({});
+                    }
+                    request_body["options"]["num_predict"] = json!(output_length(
+                        prompt,
+                        ratio,
+                        args.min_output,
+                        args.max_output
+                    ));
+                }
+                // A length in the file name is the most specific setting
+                if let Some(num_predict) =
+                    filename_max_length(file_path).filter(|_| args.parse_filename_params)
+                {
+                    if !request_body["options"].is_object() {
+                        request_body["options"] = json!({});
+                    }
+                    request_body["options"]["num_predict"] = json!(num_predict);
+                }
+                Ok(request_body)
+            })
+            .collect()
+    }
+
+    // The request body of a prompt for `model` with the run's template and params; reduce prompts are sent with
+    // just these
+    fn request_body(&self, prompt: &str, model: &str) -> Result<Value, Box<dyn Error>> {
+        build_request_body(prompt, model, self.template.as_ref(), self.params.as_ref())
+    }
+}
+
+// Sends the requests of a run, retrying them under the run's retry policy. Identical requests within a run are only
+// sent once.
+struct Sender<'a> {
+    args: &'a CliArgs,
+    log: &'a EventLog,
+    client: Client,
+    retry_policy: RetryPolicy,
+    // The --prompt-suffix, kept whole when --shrink-on-overflow cuts a prompt
+    prompt_suffix: Option<String>,
+    // Generations by request key, reused for identical requests later in the run
+    response_cache: HashMap<String, Generation>,
+    // Context returned by the last request, sent with the next one with --use-context
+    conversation_context: Option<Value>,
+}
+
+impl<'a> Sender<'a> {
+    fn new(
+        args: &'a CliArgs,
+        log: &'a EventLog,
+        prompt_suffix: Option<String>,
+    ) -> Result<Sender<'a>, Box<dyn Error>> {
+        let retry_policy = RetryPolicy {
+            retries: args.retries,
+            backoff: Duration::from_secs_f64(args.retry_backoff),
+            max_elapsed: args.max_retry_elapsed.map(Duration::from_secs_f64),
+            statuses: (!args.retry_on_status.is_empty())
+                .then(|| args.retry_on_status.iter().copied().collect()),
+        };
+        let client = build_client(args.proxy.as_deref(), args.http2, args.api_key.as_deref())
+            .map_err(|e| format!("Could not create the HTTP client: {}", e))?;
+        Ok(Sender {
+            args,
+            log,
+            client,
+            retry_policy,
+            prompt_suffix,
+            response_cache: HashMap::new(),
+            conversation_context: None,
+        })
+    }
+
+    // Send a request per prompt of a chunk file; the summaries of sub-split chunks are joined into one
+    fn summarize(
+        &mut self,
+        file_path: &Path,
+        label: &str,
+        request_bodies: &[Value],
+    ) -> Result<Generation, Box<dyn Error>> {
+        let mut generations = Vec::with_capacity(request_bodies.len());
+        for (part, request_body) in request_bodies.iter().enumerate() {
+            // Continue the conversation of the previous request
+            let mut request_body = request_body.clone();
+            if let Some(context) = &self.conversation_context {
+                request_body["context"] = context.clone();
+            }
+            let key = request_key(&request_body);
+            let generation = match self.response_cache.get(&key) {
+                Some(generation) => generation.clone(),
+                None => {
+                    let mut generation = send_shrinking(
+                        &request_body,
+                        self.args.shrink_on_overflow,
+                        self.prompt_suffix.as_deref(),
+                        self.log,
+                        label,
+                        |request_body| {
+                            self.send(request_body, label, || {
+                                partial_path(file_path, part, request_bodies.len())
+                            })
+                        },
+                    )?;
+                    if self.args.trim_incomplete {
+                        generation.response =
+                            trim_incomplete_sentence(&generation.response).to_string();
+                    }
+                    self.response_cache.insert(key, generation.clone());
+                    generation
+                }
+            };
+            if self.args.use_context {
+                self.conversation_context = generation.context.clone();
+            }
+            generations.push(generation);
+        }
+        Ok(Generation::combine(generations))
+    }
+
+    // Send a request with retries; with --stream it is streamed, the text so far being saved to the file `partial`
+    // names
+    fn send(
+        &self,
+        request_body: &Value,
+        label: &str,
+        partial: impl Fn() -> PathBuf,
+    ) -> Result<Generation, Box<dyn Error>> {
+        let timeout = request_timeout(self.args.timeout_per_token, request_body);
+        send_with_retries(&self.retry_policy, self.log, label, || {
+            if self.args.stream {
+                send_stream_request(
+                    &self.client,
+                    &self.args.api_url,
+                    request_body,
+                    timeout,
+                    &partial(),
+                    self.args.resume_partial,
+                )
+            } else {
+                send_request(
+                    &self.client,
+                    &self.args.api_url,
+                    request_body,
+                    timeout,
+                    &self.args.response_pointer,
+                )
+            }
+        })
+    }
+
+    // Send the request for a reduce prompt, returning the generated summary
+    fn send_reduce(&self, label: &str, request_body: &Value) -> Result<String, Box<dyn Error>> {
+        let timeout = request_timeout(self.args.timeout_per_token, request_body);
+        let generation = send_with_retries(&self.retry_policy, self.log, label, || {
+            send_request(
+                &self.client,
+                &self.args.api_url,
+                request_body,
+                timeout,
+                &self.args.response_pointer,
+            )
+        })?;
+        Ok(generation.response)
+    }
+}
+
+// Combine the chunk summaries, in chunk order, into one final summary with `model`
+fn reduce_results(
+    args: &CliArgs,
+    sender: &Sender,
+    settings: &RequestSettings,
+    model: &str,
+    chunk_order: &[String],
+    results: &HashMap<String, Value>,
+) -> Result<String, Box<dyn Error>> {
+    let summaries: Vec<String> = chunk_order
+        .iter()
+        .filter_map(|key| results.get(key).and_then(stored_summary))
+        .map(String::from)
+        .collect();
+    let fanout = args.reduce_fanout.unwrap_or(summaries.len().max(2));
+    reduce_summaries(summaries, fanout, |level, group| {
+        let label = format!("reduce (level {})", level);
+        sender.log.started(&label);
+        let started = Instant::now();
+        let prompt = format!(
+            "{}\n\n{}",
+            stage_reduce_prompt(args, level),
+            group.join("\n\n")
+        );
+        // The reduce prompt is sent with the chunk requests' parameters
+        let request_body = settings.request_body(&prompt, model)?;
+        let summary = sender.send_reduce(&label, &request_body)?;
+        sender.log.completed(&label, started.elapsed());
+        Ok(summary)
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = parse_args(&std::env::args().collect::<Vec<_>>()).unwrap_or_else(|e| e.exit());
+    // Report partial failure through the exit code so scripts and CI can react to it
+    if !run(args, &Console::process())? {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
+// Summarize the chunk files the arguments select, printing through `console`. Returns false when more files
+// failed than --fail-on-error allows or the run was aborted.
+fn run(mut args: CliArgs, console: &Console) -> Result<bool, Box<dyn Error>> {
+    // --count-only plans the requests like --dry-run, reporting their size instead of their bodies
+    args.dry_run |= args.count_only;
+
+    check_args(&args)?;
+    let prompt_options = prompt_options(&args)?;
+
+    // With --output -, the results go to stdout and everything else to stderr
+    let to_stdout = args.output == STDOUT_OUTPUT;
+    let log = EventLog {
+        format: args.log_format,
+        to_stderr: to_stdout,
+        console: console.clone(),
+    };
+    // Name the env file the defaults were loaded from
+    if let (Some(env_file), LogFormat::Text) = (&args.env_file, args.log_format) {
+        let loaded = format!("Loaded defaults from {}", env_file);
+        if to_stdout {
+            console.eprintln(&loaded);
+        } else {
+            console.println(&loaded);
+        }
+    }
+
+    // Run ID and start time stamped on the output with --with-metadata
+    let metadata = args
+        .with_metadata
+        .then(|| run_metadata(args.run_id.as_deref()));
+
+    let mut results: HashMap<String, Value> = HashMap::new();
+
+    // In incremental mode, start from the existing output and only process newer files
+    let mut since = None;
+    if args.since && Path::new(&args.output).exists() {
+        since = Some(fs::metadata(&args.output)?.modified()?);
+        // NDJSON lines are appended to, so the previous results do not need to be kept in memory
+        if args.output_format == OutputFormat::Json {
+            results = read_output(Path::new(&args.output))?;
+            // The previous run's metadata and combined summary are replaced, not carried over
+            results.retain(|key, _| {
+                key != RUN_ID_KEY && key != RUN_TIMESTAMP_KEY && key != COMBINED_KEY
+            });
+        }
+    }
+
+    // Fail before sending any request if the results could not be saved afterwards
+    if !args.dry_run && !to_stdout {
+        ensure_output_writable(Path::new(&args.output))
+            .map_err(|e| format!("Output file {} is not writable: {}", args.output, e))?;
+    }
+
+    // NDJSON results are written line by line as each file completes
+    let mut ndjson_output: Option<Box<dyn Write>> =
+        if args.output_format == OutputFormat::Ndjson && !args.dry_run {
+            if to_stdout {
+                Some(Box::new(console.stdout.clone()))
+            } else {
+                Some(Box::new(
+                    open_ndjson_output(Path::new(&args.output), args.since).map_err(|e| {
+                        format!("Output file {} could not be opened: {}", args.output, e)
+                    })?,
+                ))
+            }
+        } else {
+            None
+        };
+
+    let settings = RequestSettings::load(&args)?;
+    let mut sender = Sender::new(&args, &log, prompt_options.suffix.clone())?;
+
+    if args.validate_json {
+        let checked: Vec<String> = [
+            params_source(&args).as_ref().map(ParamsSource::describe),
+            args.request_template
+                .as_ref()
+                .map(|path| format!("request template {}", path)),
+            args.params_map
+                .as_ref()
+                .map(|path| format!("params map {}", path)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if checked.is_empty() {
+            console.println("No JSON inputs to validate");
+        }
+        for source in checked {
+            console.println(&format!("Valid JSON: {}", source));
+        }
+        return Ok(true);
+    }
+
+    let Chunks {
+        files: mut chunk_files,
+        base_dir,
+        zip_entries,
+    } = collect_chunks(&args, &log)?;
+    let checksum_failures = verify_checksums(&args, &mut chunk_files, &log)?;
+    let result_keys = result_keys(&chunk_files, &base_dir, args.output_key_template.as_deref())?;
+    // The reduce combines the summaries in chunk order
+    let chunk_order = result_keys.clone();
+
+    // Models every file is sent to; comparison mode nests the results per model
+    let compare_models = !args.models.is_empty();
+    // Files completed before an interrupted run are restored from the checkpoint and skipped
+    let checkpoint_path = checkpoint_path(&args.output);
+    let (completed, mut checkpoint) = if args.since_checkpoint && !args.dry_run {
+        let (completed, checkpoint) = resume_checkpoint(&checkpoint_path, &mut results)?;
+        (completed, Some(checkpoint))
+    } else {
+        (HashSet::new(), None)
+    };
+
+    let models = if compare_models {
+        args.models.clone()
+    } else {
+        vec![args
+            .model
+            .clone()
+            .expect("--model is required without --models")]
+    };
+
+    let draw_progress = draws_progress(&args, std::io::stderr().is_terminal());
+    let pb = progress_bar((chunk_files.len() * models.len()) as u64, draw_progress)?;
+
+    // Number of requests that could not be built or sent
+    let mut failures = checksum_failures;
+    // Prompt tokens and requests planned with --count-only
+    let (mut counted_tokens, mut counted_requests) = (0, 0);
+
+    // Failed requests since the last success, for --abort-after-failures
+    let mut consecutive_failures = 0;
+    let mut aborted = false;
+
+    // Summary of the last chunk summarized, carried into the next prompt with --rolling-context
+    let mut previous_summary: Option<String> = None;
+
+    // Iterate over all text files in the directory
+    'files: for (file_path, file_name) in chunk_files.into_iter().zip(result_keys) {
+        // Skip files completed before the run was interrupted
+        if completed.contains(&file_name) {
+            pb.inc(models.len() as u64);
+            continue;
+        }
+
+        // Skip files that have not changed since the previous output was written
+        if let Some(since) = since {
+            match fs::metadata(&file_path).and_then(|metadata| metadata.modified()) {
+                Ok(modified) if modified <= since => {
+                    pb.inc(models.len() as u64);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) if args.keep_going() => {
+                    failures += 1;
+                    log.failed(&file_name, Duration::ZERO, &e.to_string());
+                    pb.inc(models.len() as u64);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let content = match read_chunk_file(&file_path, zip_entries.as_ref(), args.lossy_utf8) {
+            Ok(content) => content,
+            Err(e) if args.keep_going() => {
+                failures += 1;
+                log.failed(&file_name, Duration::ZERO, &e.to_string());
+                pb.inc(models.len() as u64);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let prompts = match chunk_prompts(
+            &args,
+            &file_path,
+            &file_name,
+            content,
+            &prompt_options,
+            previous_summary.as_deref(),
+            &log,
+        ) {
+            Ok(prompts) => prompts,
+            Err(e) => {
+                failures += 1;
+                log.failed(&file_name, Duration::ZERO, &e);
+                pb.inc(models.len() as u64);
+                continue;
+            }
+        };
+        let prompt = prompts.join("\n\n");
+
+        // Send a request for each file and model
+        for model in &models {
+            // Name the model in log events when comparing several
+            let label = if compare_models {
+                format!("{} ({})", file_name, model)
+            } else {
+                file_name.clone()
+            };
+            log.started(&label);
+            let started = Instant::now();
+
+            let request_bodies =
+                settings.request_bodies(&args, model, &file_path, &file_name, &prompts);
+
+            // In dry-run mode only show what would be sent
+            if args.dry_run {
+                match &request_bodies {
+                    Ok(request_bodies) if args.count_only => {
+                        let tokens: usize = request_bodies.iter().map(prompt_tokens).sum();
+                        console.println(&format!("{}: {} tokens", label, tokens));
+                        counted_tokens += tokens;
+                        counted_requests += request_bodies.len();
+                    }
+                    Ok(request_bodies) => {
+                        for (i, request_body) in request_bodies.iter().enumerate() {
+                            print_planned_request(
+                                console,
+                                &part_label(&label, i, request_bodies.len()),
+                                request_body,
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        log.failed(&label, started.elapsed(), &e.to_string());
+                    }
+                }
+                continue;
+            }
+
+            let response = request_bodies
+                .and_then(|request_bodies| sender.summarize(&file_path, &label, &request_bodies));
+            match response {
+                Ok(generation) => {
+                    // Warn when the summary was cut off at the token limit
+                    if generation.hit_length_limit() {
+                        log.warning(
+                            "summary_cut_off",
+                            &label,
+                            "generation stopped at the token limit (done_reason \"length\")",
+                        );
+                    }
+
+                    // Flag suspiciously short summaries for review
+                    if let Some(min_ratio) = args.min_summary_ratio {
+                        let ratio = summary_ratio(&prompt, &generation.response);
+                        if ratio < min_ratio {
+                            log.warning(
+                                "summary_too_short",
+                                &label,
+                                &format!(
+                                    "summary is suspiciously short (ratio {:.3} < {})",
+                                    ratio, min_ratio
+                                ),
+                            );
+                        }
+                    }
+
+                    // Write the summary next to its chunk file
+                    if args.sidecar {
+                        if let Err(e) = fs::write(sidecar_path(&file_path), &generation.response) {
+                            log.warning(
+                                "sidecar_failed",
+                                &label,
+                                &format!("could not write summary sidecar: {}", e),
+                            );
+                        }
+                    }
+
+                    if let Some(output) = &mut ndjson_output {
+                        write_ndjson_line(
+                            output,
+                            &file_name,
+                            compare_models.then_some(model.as_str()),
+                            &generation.response,
+                            metadata.as_ref(),
+                        )?;
+                    }
+
+                    if args.rolling_context {
+                        previous_summary = Some(generation.response.clone());
+                    }
+
+                    // Tag the response with the filename (and model) and store in the hashmap
+                    if compare_models {
+                        let entry = results
+                            .entry(file_name.clone())
+                            .or_insert_with(|| json!({}));
+                        if !entry.is_object() {
+                            *entry = json!({});
+                        }
+                        entry[model.as_str()] = generation.into_result(args.keep_done_reason);
+                    } else {
+                        results.insert(
+                            file_name.clone(),
+                            generation.into_result(args.keep_done_reason),
+                        );
+                        if let Some(checkpoint) = &mut checkpoint {
+                            write_checkpoint_line(checkpoint, &file_name, &results[&file_name])?;
+                        }
+                    }
+                    log.completed(&label, started.elapsed());
+                    consecutive_failures = 0;
+                }
+                Err(e) => {
+                    failures += 1;
+                    consecutive_failures += 1;
+                    log.failed(&label, started.elapsed(), &e.to_string());
+                }
+            }
+            pb.inc(1);
+
+            // Stop early when the server keeps failing rather than failing every remaining file
+            if args
+                .abort_after_failures
+                .is_some_and(|limit| consecutive_failures >= limit)
+            {
+                log.warning(
+                    "run_aborted",
+                    &label,
+                    &format!(
+                        "aborting after {} consecutive failures; the remaining files are not processed",
+                        consecutive_failures
+                    ),
+                );
+                aborted = true;
+                break 'files;
+            }
+        }
+    }
+
+    if aborted {
+        pb.abandon();
+    } else {
+        pb.finish_with_message("All files processed.");
+    }
+    if !draw_progress && args.log_format == LogFormat::Text && !args.dry_run {
+        let processed = format!(
+            "Processed {}/{} files, {} failed.",
+            pb.position(),
+            pb.length().unwrap_or(0),
+            failures
+        );
+        if to_stdout {
+            console.eprintln(&processed);
+        } else {
+            console.println(&processed);
+        }
+    }
+
+    if args.count_only {
+        console.println(&format!(
+            "Total: {} prompt tokens in {} requests",
+            counted_tokens, counted_requests
+        ));
+    }
+
+    // Combine the summaries into one final summary
+    if args.reduce() && !args.dry_run && !aborted {
+        let reduced = reduce_results(
+            &args,
+            &sender,
+            &settings,
+            &models[0],
+            &chunk_order,
+            &results,
+        );
+        let written = reduced.and_then(|summary| {
+            if let Some(reduce_path) = &args.reduce {
+                fs::write(reduce_path, &summary)?;
+            }
+            if args.reduce_inline {
+                if let Some(output) = &mut ndjson_output {
+                    write_ndjson_line(output, COMBINED_KEY, None, &summary, metadata.as_ref())?;
+                }
+                results.insert(COMBINED_KEY.to_string(), json!(summary));
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            failures += 1;
+            log.failed("reduce", Duration::ZERO, &e.to_string());
+        }
+    }
+
+    if !args.dry_run && ndjson_output.is_none() {
+        if let Some(metadata) = &metadata {
+            results.extend(metadata.clone());
+        }
+        write_output(Path::new(&args.output), &results, console)?;
+    }
+
+    // A run that completed every file has nothing left to resume
+    if checkpoint.is_some() && failures == 0 && !aborted {
+        fs::remove_file(&checkpoint_path)?;
+    }
+
+    Ok(failures <= args.fail_on_error && !aborted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // Where the mock server is addressed, like an Ollama server
+    const MOCK_PATH: &str = "/api/generate";
+
+    // An Ollama response carrying the summary
+    fn reply_body(text: &str) -> Value {
+        json!({ "response": text, "done": true })
+    }
+
+    // The prompt sent for a chunk with the given options
+    fn sent_prompt(text: &str, options: &PromptOptions) -> Value {
+        let (prompts, _) = prepare_prompts(text, options, None);
+        let request_body = build_request_body(&prompts[0], "phi3", None, None).unwrap();
+        request_body["prompt"].clone()
+    }
+
+    // A request received by the mock server
+    #[derive(Clone, Debug)]
+    struct Received {
+        // The request line's target: the path, or the whole URL when sent through a proxy
+        target: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        fn prompt(&self) -> String {
+            let body: Value = serde_json::from_str(&self.body).unwrap();
+            body["prompt"].as_str().unwrap().to_string()
+        }
+    }
+
+    // The mock server's answer to a request
+    struct Reply {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+        // Number of body bytes sent before the connection is dropped, for an interrupted response
+        cut_after: Option<usize>,
+    }
+
+    impl Reply {
+        fn json(body: Value) -> Reply {
+            Reply {
+                status: 200,
+                headers: Vec::new(),
+                body: body.to_string(),
+                cut_after: None,
+            }
+        }
+
+        fn summary(text: &str) -> Reply {
+            Reply::json(reply_body(text))
+        }
+
+        fn status(status: u16) -> Reply {
+            Reply {
+                status,
+                headers: Vec::new(),
+                body: json!({ "error": "mock failure" }).to_string(),
+                cut_after: None,
+            }
+        }
+
+        // An Ollama stream sending each token as a JSON line, followed by the final object
+        fn stream(tokens: &[&str]) -> Reply {
+            let mut body: String = tokens
+                .iter()
+                .map(|token| format!("{}\n", json!({ "response": token, "done": false })))
+                .collect();
+            body.push_str(&format!(
+                "{}\n",
+                json!({ "response": "", "done": true, "done_reason": "stop" })
+            ));
+            Reply {
+                status: 200,
+                headers: Vec::new(),
+                body,
+                cut_after: None,
+            }
+        }
+
+        fn header(mut self, name: &str, value: &str) -> Reply {
+            self.headers.push((name.to_string(), value.to_string()));
+            self
+        }
+
+        // Drop the connection after the first `lines` lines of the body
+        fn interrupted_after(mut self, lines: usize) -> Reply {
+            let cut = self.body.match_indices('\n').nth(lines - 1).unwrap().0 + 1;
+            self.cut_after = Some(cut);
+            self
+        }
+    }
+
+    type Respond = dyn Fn(usize, &Received) -> Reply + Send + Sync;
+
+    // An HTTP/1.1 server on a local port that answers each request with `respond`, given the request's index in
+    // arrival order. Connections are kept alive like a real server's.
+    struct MockServer {
+        address: std::net::SocketAddr,
+        received: Arc<Mutex<Vec<Received>>>,
+        // Number of connections accepted
+        connections: Arc<AtomicUsize>,
+    }
+
+    impl MockServer {
+        fn start(
+            respond: impl Fn(usize, &Received) -> Reply + Send + Sync + 'static,
+        ) -> MockServer {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let respond: Arc<Respond> = Arc::new(respond);
+            let connections = Arc::new(AtomicUsize::new(0));
+            let (shared_received, shared_connections) = (received.clone(), connections.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    shared_connections.fetch_add(1, Ordering::SeqCst);
+                    let (received, respond) = (shared_received.clone(), respond.clone());
+                    thread::spawn(move || serve_connection(stream, &received, respond.as_ref()));
+                }
+            });
+            MockServer {
+                address,
+                received,
+                connections,
+            }
+        }
+
+        fn url(&self) -> String {
+            format!("http://{}{}", self.address, MOCK_PATH)
+        }
+
+        fn requests(&self) -> Vec<Received> {
+            self.received.lock().unwrap().clone()
+        }
+
+        fn connections(&self) -> usize {
+            self.connections.load(Ordering::SeqCst)
+        }
+    }
+
+    fn serve_connection(stream: TcpStream, received: &Mutex<Vec<Received>>, respond: &Respond) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                return;
+            }
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                match line.trim_end().split_once(':') {
+                    Some((name, value)) => {
+                        headers.push((name.to_string(), value.trim().to_string()))
+                    }
+                    None => break,
+                }
+            }
+            let length = headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_, value)| value.parse().unwrap());
+            let mut body = vec![0; length];
+            if reader.read_exact(&mut body).is_err() {
+                return;
+            }
+            let request = Received {
+                target: request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string(),
+                headers,
+                body: String::from_utf8(body).unwrap(),
+            };
+            let index = {
+                let mut received = received.lock().unwrap();
+                received.push(request.clone());
+                received.len() - 1
+            };
+            let reply = respond(index, &request);
+            let mut response = format!(
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+                reply.status,
+                reply.body.len()
+            );
+            for (name, value) in &reply.headers {
+                response.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            response.push_str("\r\n");
+            match reply.cut_after {
+                Some(cut) => {
+                    response.push_str(&reply.body[..cut]);
+                    let _ = stream.write_all(response.as_bytes());
+                    return;
+                }
+                None => response.push_str(&reply.body),
+            }
+            if stream.write_all(response.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    // A directory holding the given files, creating their parent directories
+    fn chunk_dir(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    // What a run returned and printed
+    struct RunOutput {
+        result: Result<bool, String>,
+        stdout: String,
+        stderr: String,
+    }
+
+    // Run the CLI in-process with the given arguments, capturing its output
+    fn run_cli(args: &[&str]) -> RunOutput {
+        run_parsed(
+            CliArgs::from_iter_safe(std::iter::once("summarizer").chain(args.iter().copied()))
+                .unwrap(),
+        )
+    }
+
+    // Run already parsed arguments with a buffered console
+    fn run_parsed(args: CliArgs) -> RunOutput {
+        let stdout = Rc::new(RefCell::new(Vec::new()));
+        let stderr = Rc::new(RefCell::new(Vec::new()));
+        let console = Console {
+            stdout: OutputStream(stdout.clone()),
+            stderr: OutputStream(stderr.clone()),
+        };
+        let result = run(args, &console).map_err(|e| e.to_string());
+        let text = |buffer: Rc<RefCell<Vec<u8>>>| String::from_utf8(buffer.take()).unwrap();
+        RunOutput {
+            result,
+            stdout: text(stdout),
+            stderr: text(stderr),
+        }
+    }
+
+    // Run the CLI against `server` with the phi3 model and the given arguments
+    fn run_against(server: &MockServer, args: &[&str]) -> RunOutput {
+        let url = server.url();
+        let mut args = args.to_vec();
+        args.extend(["--url", &url, "--model", "phi3"]);
+        run_cli(&args)
+    }
+
+    // Summarize the chunk files in `dir` against `server`, writing the results to summaries.json in `dir`
+    fn run_on(dir: &Path, server: &MockServer, extra: &[&str]) -> RunOutput {
+        let output = dir.join("summaries.json");
+        let mut args = vec![
+            "--dir",
+            dir.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+        ];
+        args.extend(extra);
+        run_against(server, &args)
+    }
+
+    // The results written by run_on
+    fn results_in(dir: &Path) -> Value {
+        serde_json::from_str(&fs::read_to_string(dir.join("summaries.json")).unwrap()).unwrap()
+    }
+
+    // The JSON log lines of a run
+    fn log_events(output: &str) -> Vec<Value> {
+        output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn newlines_are_preserved_by_default() {
+        let text = "Speaker 1: Hello\nSpeaker 2: Hi there\n\nSpeaker 1: Bye";
+        assert_eq!(sent_prompt(text, &PromptOptions::default()), text);
+    }
+
+    #[test]
+    fn collapse_newlines_joins_lines_with_spaces() {
+        let text = "Speaker 1: Hello\nSpeaker 2: Hi there\n\nSpeaker 1: Bye";
+        let options = PromptOptions {
+            collapse_newlines: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            sent_prompt(text, &options),
+            "Speaker 1: Hello Speaker 2: Hi there Speaker 1: Bye"
+        );
+    }
+
+    #[test]
+    fn json_log_emits_one_object_per_event() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[
+            ("part_001.txt", "First chunk."),
+            ("part_002.txt", "Second chunk."),
+        ]);
+        let run = run_on(dir.path(), &server, &["--log-format", "json"]);
+        assert_eq!(run.result, Ok(true));
+        let mut prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        prompts.sort();
+        assert_eq!(prompts, ["First chunk.", "Second chunk."]);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["part_002.txt"]), Some("A summary."));
+        let events = log_events(&run.stdout);
+        assert_eq!(events.len(), 4);
+        for event in &events {
+            assert!(event["timestamp"].is_string());
+            assert!(event["filename"].as_str().unwrap().starts_with("part_00"));
+            assert_eq!(event["status"], "ok");
+        }
+        let completed: Vec<&Value> = events
+            .iter()
+            .filter(|event| event["event"] == "file_completed")
+            .collect();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.iter().all(|event| event["duration_ms"].is_u64()));
+    }
+
+    fn sha256_hex(text: &str) -> String {
+        Sha256::digest(text.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[test]
+    fn checksum_mismatch_is_logged_as_a_warning_event() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[
+            ("part_001.txt", "First chunk."),
+            ("part_002.txt", "Second chunk."),
+        ]);
+        let manifest = dir.path().join("checksums.sha256");
+        // part_002.txt was changed after the manifest was written
+        fs::write(
+            &manifest,
+            format!(
+                "{}  part_001.txt\n{}  part_002.txt\n",
+                sha256_hex("First chunk."),
+                sha256_hex("Original chunk.")
+            ),
+        )
+        .unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--checksum-manifest",
+                manifest.to_str().unwrap(),
+                "--on-checksum-mismatch",
+                "skip",
+                "--log-format",
+                "json",
+            ],
+        );
+        assert_eq!(run.result, Ok(false));
+        assert_eq!(server.requests().len(), 1);
+        let events = log_events(&run.stdout);
+        let mismatch = events
+            .iter()
+            .find(|event| event["event"] == "checksum_mismatch")
+            .unwrap();
+        assert_eq!(mismatch["status"], "warning");
+        assert!(mismatch["filename"]
+            .as_str()
+            .unwrap()
+            .ends_with("part_002.txt"));
+        assert!(run.stderr.is_empty());
+    }
+
+    #[test]
+    fn context_file_precedes_the_chunk_text() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "The chunk text.")]);
+        let context = dir.path().join("context.md");
+        fs::write(&context, "This is episode 5 of a podcast.\n").unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--context-file", context.to_str().unwrap()],
+        );
+        assert_eq!(run.result, Ok(true));
+        let prompt = server.requests()[0].prompt();
+        let context_at = prompt.find("This is episode 5 of a podcast.").unwrap();
+        assert!(context_at < prompt.find("The chunk text.").unwrap());
+    }
+
+    #[test]
+    fn short_summary_of_a_long_prompt_is_flagged() {
+        let server = MockServer::start(|_, request| {
+            // The long chunk gets a one-word summary
+            if request.prompt().len() > 100 {
+                Reply::summary("Ok.")
+            } else {
+                Reply::summary("A fair summary of a short chunk.")
+            }
+        });
+        let long_chunk = "The speakers discuss the quarterly results in detail. ".repeat(20);
+        let dir = chunk_dir(&[
+            ("long.txt", &long_chunk),
+            ("short.txt", "Brief remarks on the weather today."),
+        ]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--min-summary-ratio", "0.1", "--log-format", "json"],
+        );
+        assert_eq!(run.result, Ok(true));
+        let flagged: Vec<Value> = log_events(&run.stdout)
+            .into_iter()
+            .filter(|event| event["event"] == "summary_too_short")
+            .collect();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0]["filename"], "long.txt");
+        assert_eq!(flagged[0]["status"], "warning");
+    }
+
+    #[test]
+    fn sidecar_files_hold_each_chunk_summary() {
+        let server = MockServer::start(|_, request| {
+            Reply::summary(&format!("Summary of {}", request.prompt()))
+        });
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let run = run_on(dir.path(), &server, &["--sidecar"]);
+        assert_eq!(run.result, Ok(true));
+        let sidecar = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(sidecar("part_001.summary.txt"), "Summary of first");
+        assert_eq!(sidecar("part_002.summary.txt"), "Summary of second");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    // Set a file's modification time to `age` before now
+    fn set_age(path: &Path, age: Duration) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn since_only_processes_files_newer_than_the_output() {
+        let server = MockServer::start(|_, request| {
+            Reply::summary(&format!("Summary of {}", request.prompt()))
+        });
+        let dir = chunk_dir(&[("old.txt", "old chunk"), ("new.txt", "new chunk")]);
+        let output = dir.path().join("summaries.json");
+        fs::write(&output, r#"{"old.txt": "Earlier summary"}"#).unwrap();
+        set_age(&dir.path().join("old.txt"), Duration::from_secs(7200));
+        set_age(&output, Duration::from_secs(3600));
+
+        let run = run_on(dir.path(), &server, &["--since"]);
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts, ["new chunk"]);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["old.txt"]), Some("Earlier summary"));
+        assert_eq!(
+            stored_summary(&results["new.txt"]),
+            Some("Summary of new chunk")
+        );
+    }
+
+    #[test]
+    fn prompt_suffix_ends_the_prompt() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "  The chunk text.\n\n")]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--prompt-suffix", "Write a concise summary:"],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(
+            server.requests()[0].prompt(),
+            "The chunk text.\n\nWrite a concise summary:"
+        );
+    }
+
+    #[test]
+    fn byte_order_mark_is_not_sent() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "\u{feff}The chunk text.")]);
+        assert_eq!(run_on(dir.path(), &server, &[]).result, Ok(true));
+        assert_eq!(server.requests()[0].prompt(), "The chunk text.");
+    }
+
+    #[test]
+    fn dry_run_prints_each_planned_request_without_sending_it() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let run = run_on(dir.path(), &server, &["--dry-run"]);
+        assert_eq!(run.result, Ok(true));
+        assert!(server.requests().is_empty());
+        assert!(!dir.path().join("summaries.json").exists());
+        let mut planned: Vec<&str> = run
+            .stdout
+            .lines()
+            .filter(|line| line.ends_with(".txt:"))
+            .collect();
+        planned.sort();
+        assert_eq!(planned, ["part_001.txt:", "part_002.txt:"]);
+        assert!(run.stdout.contains("\"prompt\": \"first\""));
+    }
+
+    // The request the server received for the chunk with this text
+    fn request_for(server: &MockServer, chunk_text: &str) -> Value {
+        let request = server
+            .requests()
+            .into_iter()
+            .find(|request| request.prompt() == chunk_text)
+            .unwrap();
+        serde_json::from_str(&request.body).unwrap()
+    }
+
+    #[test]
+    fn params_map_overrides_the_params_for_matching_files() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[
+            ("talk_part_001.txt", "first"),
+            ("talk_part_002.txt", "second"),
+        ]);
+        let params = dir.path().join("params.json");
+        fs::write(
+            &params,
+            r#"{"options": {"num_predict": 150, "temperature": 0.2}}"#,
+        )
+        .unwrap();
+        let params_map = dir.path().join("params_map.json");
+        fs::write(
+            &params_map,
+            r#"{"*_002.txt": {"options": {"num_predict": 300}}}"#,
+        )
+        .unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--params",
+                params.to_str().unwrap(),
+                "--params-map",
+                params_map.to_str().unwrap(),
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        // Nested objects are merged key by key, the params map overriding the params
+        assert_eq!(
+            request_for(&server, "first")["options"],
+            json!({"num_predict": 150, "temperature": 0.2})
+        );
+        assert_eq!(
+            request_for(&server, "second")["options"],
+            json!({"num_predict": 300, "temperature": 0.2})
+        );
+        assert_eq!(request_for(&server, "first")["model"], "phi3");
+    }
+
+    #[test]
+    fn params_cannot_override_the_prompt_model_or_stream() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "first")]);
+        let params = dir.path().join("params.json");
+        fs::write(
+            &params,
+            r#"{"prompt": "Ignore the text.", "temperature": 0.2}"#,
+        )
+        .unwrap();
+        let run = run_on(dir.path(), &server, &["--params", params.to_str().unwrap()]);
+        assert_eq!(
+            run.result,
+            Err(format!(
+                "params file {} sets \"prompt\", which the tool sets itself and cannot be overridden",
+                params.display()
+            ))
+        );
+
+        let params_map = dir.path().join("params_map.json");
+        fs::write(&params_map, r#"{"*.txt": {"stream": true}}"#).unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--params-map", params_map.to_str().unwrap()],
+        );
+        assert_eq!(
+            run.result,
+            Err(format!(
+                "params map {} pattern *.txt sets \"stream\", which the tool sets itself and cannot be overridden",
+                params_map.display()
+            ))
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn oversized_chunk_is_truncated_at_a_word_boundary() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "alpha beta gamma\ndelta epsilon zeta eta")]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--truncate",
+                "--max-prompt-tokens",
+                "4",
+                "--log-format",
+                "json",
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests()[0].prompt(), "alpha beta gamma\ndelta");
+        let truncated = log_events(&run.stdout)
+            .into_iter()
+            .find(|event| event["event"] == "prompt_truncated")
+            .unwrap();
+        assert_eq!(truncated["status"], "warning");
+    }
+
+    #[test]
+    fn word_boundary_truncation_never_cuts_a_word() {
+        assert_eq!(
+            truncate_at_word_boundary("one two  three four", 2),
+            Some("one two")
+        );
+        assert_eq!(truncate_at_word_boundary("one two", 2), None);
+        assert_eq!(
+            truncate_at_word_boundary("  one two three", 1),
+            Some("  one")
+        );
+    }
+
+    #[test]
+    fn failed_file_fails_the_run_unless_tolerated() {
+        let server = MockServer::start(|_, request| {
+            if request.prompt() == "bad" {
+                Reply::status(500)
+            } else {
+                Reply::summary("A summary.")
+            }
+        });
+        let dir = chunk_dir(&[("part_001.txt", "good"), ("part_002.txt", "bad")]);
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(false));
+        assert!(run.stdout.contains("Error processing part_002.txt: "));
+        assert!(run.stdout.contains("Processed 2/2 files, 1 failed."));
+        // The results of the other files are still written
+        assert!(results_in(dir.path()).get("part_001.txt").is_some());
+
+        let run = run_on(dir.path(), &server, &["--fail-on-error", "1"]);
+        assert_eq!(run.result, Ok(true));
+    }
+
+    #[test]
+    fn setup_error_is_returned_as_an_error() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        // --dir names a file rather than a directory of chunks
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let run = run_on(&dir.path().join("part_001.txt"), &server, &[]);
+        assert!(run.result.is_err());
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn manifest_selects_the_files_and_their_order() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let files: Vec<(String, String)> = (1..=5)
+            .map(|n| (format!("part_00{}.txt", n), format!("chunk {}", n)))
+            .collect();
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(name, text)| (name.as_str(), text.as_str()))
+            .collect();
+        let dir = chunk_dir(&files);
+        let output = dir.path().join("summaries.json");
+        for (manifest_name, manifest) in [
+            ("manifest.txt", "part_004.txt\n# skipped\npart_002.txt\n"),
+            ("manifest.json", r#"["part_004.txt", "part_002.txt"]"#),
+        ] {
+            let manifest_path = dir.path().join(manifest_name);
+            fs::write(&manifest_path, manifest).unwrap();
+            let run = run_against(
+                &server,
+                &[
+                    "--manifest",
+                    manifest_path.to_str().unwrap(),
+                    "--output",
+                    output.to_str().unwrap(),
+                ],
+            );
+            assert_eq!(run.result, Ok(true));
+        }
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts, ["chunk 4", "chunk 2", "chunk 4", "chunk 2"]);
+        let results = results_in(dir.path());
+        let mut keys: Vec<&String> = results.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["part_002.txt", "part_004.txt"]);
+    }
+
+    #[test]
+    fn missing_output_directories_are_created() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let output = dir.path().join("results/2024/summaries.json");
+        let run = run_against(
+            &server,
+            &[
+                "--dir",
+                dir.path().to_str().unwrap(),
+                "--output",
+                output.to_str().unwrap(),
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        let results: Value = serde_json::from_str(&fs::read_to_string(output).unwrap()).unwrap();
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+    }
+
+    #[test]
+    fn unwritable_output_fails_before_any_request() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        // The output's parent is a file, so the output can never be written
+        let output = dir.path().join("part_001.txt/summaries.json");
+        let run = run_against(
+            &server,
+            &[
+                "--dir",
+                dir.path().to_str().unwrap(),
+                "--output",
+                output.to_str().unwrap(),
+            ],
+        );
+        assert!(run.result.unwrap_err().contains("is not writable"));
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn models_stores_a_summary_per_model_for_each_file() {
+        let server = MockServer::start(|_, request| {
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            Reply::summary(&format!(
+                "{} on {}",
+                body["model"].as_str().unwrap(),
+                request.prompt()
+            ))
+        });
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let output = dir.path().join("summaries.json");
+        let url = server.url();
+        let run = run_cli(&[
+            "--dir",
+            dir.path().to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--url",
+            &url,
+            "--models",
+            "phi3,llama3",
+        ]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests().len(), 4);
+        assert_eq!(
+            results_in(dir.path()),
+            json!({
+                "part_001.txt": { "phi3": "phi3 on first", "llama3": "llama3 on first" },
+                "part_002.txt": { "phi3": "phi3 on second", "llama3": "llama3 on second" }
+            })
+        );
+    }
+
+    #[test]
+    fn compressed_output_round_trips_for_since() {
+        for (extension, magic) in [
+            ("gz", &[0x1f, 0x8b][..]),
+            ("zst", &[0x28, 0xb5, 0x2f, 0xfd]),
+        ] {
+            let server = MockServer::start(|_, _| Reply::summary("New summary"));
+            let dir = chunk_dir(&[("old.txt", "old chunk"), ("new.txt", "new chunk")]);
+            let output = dir.path().join(format!("summaries.json.{}", extension));
+            let earlier = HashMap::from([("old.txt".to_string(), json!("Earlier summary"))]);
+            write_output(&output, &earlier, &Console::process()).unwrap();
+            assert!(fs::read(&output).unwrap().starts_with(magic));
+            assert_eq!(read_output(&output).unwrap(), earlier);
+            set_age(&dir.path().join("old.txt"), Duration::from_secs(7200));
+            set_age(&output, Duration::from_secs(3600));
+
+            let run = run_against(
+                &server,
+                &[
+                    "--dir",
+                    dir.path().to_str().unwrap(),
+                    "--output",
+                    output.to_str().unwrap(),
+                    "--since",
+                ],
+            );
+            assert_eq!(run.result, Ok(true));
+            assert_eq!(server.requests().len(), 1);
+            let results = read_output(&output).unwrap();
+            assert_eq!(stored_summary(&results["old.txt"]), Some("Earlier summary"));
+            assert_eq!(stored_summary(&results["new.txt"]), Some("New summary"));
+        }
+    }
+
+    #[test]
+    fn recursive_files_sharing_a_name_keep_both_summaries() {
+        let server = MockServer::start(|_, request| {
+            Reply::summary(&format!("Summary of {}", request.prompt()))
+        });
+        let dir = chunk_dir(&[
+            ("monday/part_001.txt", "monday chunk"),
+            ("tuesday/part_001.txt", "tuesday chunk"),
+            ("intro.txt", "intro chunk"),
+        ]);
+        let run = run_on(dir.path(), &server, &["--recursive"]);
+        assert_eq!(run.result, Ok(true));
+        let results = results_in(dir.path());
+        assert_eq!(
+            stored_summary(&results["monday/part_001.txt"]),
+            Some("Summary of monday chunk")
+        );
+        assert_eq!(
+            stored_summary(&results["tuesday/part_001.txt"]),
+            Some("Summary of tuesday chunk")
+        );
+        // Unique names keep their bare file name
+        assert_eq!(
+            stored_summary(&results["intro.txt"]),
+            Some("Summary of intro chunk")
+        );
+    }
+
+    #[test]
+    fn prompt_json_pointer_sends_the_field_of_json_chunks() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[
+            (
+                "part_001.json",
+                r#"{"speaker": "Ana", "content": "json chunk text"}"#,
+            ),
+            ("part_002.txt", "plain chunk text"),
+        ]);
+        let run = run_on(dir.path(), &server, &["--prompt-json-pointer", "/content"]);
+        assert_eq!(run.result, Ok(true));
+        let mut prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        prompts.sort();
+        assert_eq!(prompts, ["json chunk text", "plain chunk text"]);
+    }
+
+    #[test]
+    fn progress_bar_is_only_drawn_on_a_terminal() {
+        let args = |extra: &[&str]| {
+            let mut args = vec![
+                "summarizer",
+                "--url",
+                "http://localhost:11434",
+                "--model",
+                "phi3",
+                "--dir",
+                "chunks",
+                "--output",
+                "out.json",
+            ];
+            args.extend(extra);
+            CliArgs::from_iter_safe(args).unwrap()
+        };
+        assert!(draws_progress(&args(&[]), true));
+        assert!(!draws_progress(&args(&[]), false));
+        assert!(!draws_progress(&args(&["--no-progress"]), true));
+        assert!(!draws_progress(&args(&["--log-format", "json"]), true));
+
+        // Without a bar, the hidden bar's counts are printed when the run ends
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(run.stdout, "Processed 2/2 files, 0 failed.\n");
+        assert_eq!(run.stderr, "");
+    }
+
+    #[test]
+    fn retry_after_of_a_429_sets_the_wait_before_the_retry() {
+        let server = MockServer::start(|index, _| match index {
+            0 => Reply::status(429).header("Retry-After", "1"),
+            _ => Reply::summary("A summary."),
+        });
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let started = Instant::now();
+        // The backoff alone would wait far longer than the header asks
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--retries",
+                "1",
+                "--retry-backoff",
+                "20",
+                "--log-format",
+                "json",
+            ],
+        );
+        let elapsed = started.elapsed();
+        assert_eq!(run.result, Ok(true));
+        assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(10));
+        assert_eq!(server.requests().len(), 2);
+        let retry = log_events(&run.stdout)
+            .into_iter()
+            .find(|event| event["event"] == "retry")
+            .unwrap();
+        assert_eq!(
+            (&retry["filename"], &retry["attempt"], &retry["delay_ms"]),
+            (&json!("part_001.txt"), &json!(1), &json!(1000))
+        );
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("A summary.")
+        );
+    }
+
+    #[test]
+    fn sub_split_summaries_are_joined_into_the_entry() {
+        let server = MockServer::start(|_, request| {
+            Reply::summary(&format!("Summary of {}.", request.prompt()))
+        });
+        let dir = chunk_dir(&[("part_001.txt", "one two three four five six seven")]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--sub-split", "--max-prompt-tokens", "3"],
+        );
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts, ["one two three", "four five six", "seven"]);
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("Summary of one two three.\n\nSummary of four five six.\n\nSummary of seven.")
+        );
+    }
+
+    #[test]
+    fn done_reason_length_warns_that_the_summary_was_cut_off() {
+        let server = MockServer::start(|_, request| {
+            let done_reason = if request.prompt() == "long" {
+                "length"
+            } else {
+                "stop"
+            };
+            Reply::json(
+                json!({ "response": "A summary", "done": true, "done_reason": done_reason }),
+            )
+        });
+        let dir = chunk_dir(&[("part_001.txt", "long"), ("part_002.txt", "short")]);
+        let run = run_on(dir.path(), &server, &["--keep-done-reason"]);
+        assert_eq!(run.result, Ok(true));
+        assert!(run.stdout.contains(
+            "Warning for part_001.txt: generation stopped at the token limit (done_reason \"length\")"
+        ));
+        assert!(!run.stdout.contains("Warning for part_002.txt"));
+        let results = results_in(dir.path());
+        assert_eq!(
+            results["part_001.txt"],
+            json!({ "response": "A summary", "done_reason": "length" })
+        );
+        assert_eq!(
+            results["part_002.txt"],
+            json!({ "response": "A summary", "done_reason": "stop" })
+        );
+    }
+
+    #[test]
+    fn ndjson_output_has_one_object_per_processed_file() {
+        let server = MockServer::start(|_, request| {
+            Reply::summary(&format!("Summary of {}", request.prompt()))
+        });
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let output = dir.path().join("summaries.ndjson");
+        let run = run_against(
+            &server,
+            &[
+                "--dir",
+                dir.path().to_str().unwrap(),
+                "--output",
+                output.to_str().unwrap(),
+                "--output-format",
+                "ndjson",
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        let mut lines: Vec<Value> = fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        lines.sort_by_key(|line| line["file"].to_string());
+        assert_eq!(
+            lines,
+            [
+                json!({ "file": "part_001.txt", "summary": "Summary of first" }),
+                json!({ "file": "part_002.txt", "summary": "Summary of second" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn retries_stop_once_the_elapsed_cap_would_be_exceeded() {
+        let policy = RetryPolicy {
+            retries: 10,
+            backoff: Duration::from_millis(500),
+            max_elapsed: Some(Duration::from_secs(1)),
+            statuses: None,
+        };
+        let log = EventLog {
+            format: LogFormat::Json,
+            to_stderr: false,
+            console: Console::process(),
+        };
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result: Result<(), _> = send_with_retries(&policy, &log, "part_001.txt", || {
+            attempts += 1;
+            Err(StatusError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                retry_after: None,
+                context_overflow: false,
+            }
+            .into())
+        });
+        assert!(result.is_err());
+        // The first wait is 400-600ms with jitter; the second, twice as long, would pass the cap
+        assert_eq!(attempts, 2);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(400) && elapsed < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn front_matter_order_sets_the_processing_order() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[
+            ("intro.txt", "---\norder: 3\n---\nthird chunk"),
+            ("zeta.txt", "---\norder: 1\n---\nfirst chunk"),
+            // Without front-matter, the number in the filename is the order
+            ("part_2.txt", "second chunk"),
+        ]);
+        let run = run_on(dir.path(), &server, &["--order-from", "frontmatter"]);
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts, ["first chunk", "second chunk", "third chunk"]);
+    }
+
+    #[test]
+    fn sanitize_control_strips_control_characters_from_the_prompt() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "Hello\u{0} wor\u{1b}ld\u{7f}\tend\nline")]);
+        let run = run_on(dir.path(), &server, &["--sanitize-control"]);
+        assert_eq!(run.result, Ok(true));
+        let requests = server.requests();
+        assert!(!requests[0].body.contains("\\u0000"));
+        // The body is valid JSON, keeping newlines and tabs
+        assert_eq!(requests[0].prompt(), "Hello world\tend\nline");
+    }
+
+    #[test]
+    fn max_length_ratio_sizes_the_output_from_the_prompt() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let words = |count: usize| vec!["word"; count].join(" ");
+        let (medium, long, short) = (words(40), words(200), words(8));
+        let dir = chunk_dir(&[
+            ("part_001.txt", &medium),
+            ("part_002.txt", &long),
+            ("part_003.txt", &short),
+        ]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--max-length-ratio",
+                "0.25",
+                "--min-output",
+                "5",
+                "--max-output",
+                "30",
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        // 25% of 40, 200 and 8 tokens, the last two clamped to the ceiling and the floor
+        assert_eq!(
+            request_for(&server, &medium)["options"]["num_predict"],
+            json!(10)
+        );
+        assert_eq!(
+            request_for(&server, &long)["options"]["num_predict"],
+            json!(30)
+        );
+        assert_eq!(
+            request_for(&server, &short)["options"]["num_predict"],
+            json!(5)
+        );
+    }
+
+    #[test]
+    fn proxy_routes_the_requests_through_it() {
+        let proxy = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let output = dir.path().join("summaries.json");
+        let proxy_url = format!("http://{}", proxy.address);
+        let url = format!("http://summarizer.invalid{}", MOCK_PATH);
+        let run = run_cli(&[
+            "--dir",
+            dir.path().to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--url",
+            &url,
+            "--model",
+            "phi3",
+            "--proxy",
+            &proxy_url,
+        ]);
+        assert_eq!(run.result, Ok(true));
+        // A proxy is sent the absolute URL of the API; summarizer.invalid itself never resolves
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].target, url);
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("A summary.")
+        );
+    }
+
+    #[test]
+    fn one_connection_is_reused_across_files() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[test]
+    fn language_instruction_precedes_the_chunk_text() {
+        let server = MockServer::start(|_, _| Reply::summary("Un résumé."));
+        let dir = chunk_dir(&[("part_001.txt", "chunk text")]);
+        let run = run_on(dir.path(), &server, &["--language", "French"]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(
+            server.requests()[0].prompt(),
+            "Summarize in French:\n\nchunk text"
+        );
+
+        let server = MockServer::start(|_, _| Reply::summary("Un résumé."));
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--language",
+                "French",
+                "--language-template",
+                "Write the summary in {language}.",
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(
+            server.requests()[0].prompt(),
+            "Write the summary in French.\n\nchunk text"
+        );
+    }
+
+    #[test]
+    fn tampered_chunk_fails_verification_against_the_splitter_manifest() {
+        let dir = chunk_dir(&[
+            ("part_001.txt", "First chunk."),
+            ("part_002.txt", "Second chunk."),
+        ]);
+        let names = ["part_001.txt".to_string(), "part_002.txt".to_string()];
+        transcript_splitter::write_checksum_manifest(dir.path().to_str().unwrap(), &names, &[])
+            .unwrap();
+        let manifest = dir.path().join(transcript_splitter::CHECKSUM_MANIFEST_NAME);
+        let manifest_args = ["--checksum-manifest", manifest.to_str().unwrap()];
+
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &manifest_args);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests().len(), 2);
+
+        fs::write(dir.path().join("part_002.txt"), "Tampered chunk.").unwrap();
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &manifest_args);
+        assert_eq!(
+            run.result,
+            Err("Checksum verification failed for 1 chunk files".to_string())
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn env_file_supplies_the_url_and_api_key() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let env_file = dir.path().join(".env");
+        fs::write(
+            &env_file,
+            format!(
+                "SUMMARIZER_URL={}\nSUMMARIZER_API_KEY=secret\n",
+                server.url()
+            ),
+        )
+        .unwrap();
+        let output = dir.path().join("summaries.json");
+        let args = parse_args(
+            &[
+                "summarizer",
+                "--dir",
+                dir.path().to_str().unwrap(),
+                "--output",
+                output.to_str().unwrap(),
+                "--env-file",
+                env_file.to_str().unwrap(),
+                "--model",
+                "phi3",
+            ]
+            .map(String::from),
+        )
+        .unwrap();
+        let run = run_parsed(args);
+        assert_eq!(run.result, Ok(true));
+        assert!(run.stdout.contains("Loaded defaults from"));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret"));
+    }
+
+    #[test]
+    fn trim_incomplete_drops_the_trailing_fragment() {
+        let server = MockServer::start(|_, _| Reply::summary("A full sentence. Then it was cut"));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let run = run_on(dir.path(), &server, &["--trim-incomplete"]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("A full sentence.")
+        );
+    }
+
+    #[test]
+    fn rolling_context_carries_the_previous_summary() {
+        let server =
+            MockServer::start(|index, _| Reply::summary(&format!("Summary {}.", index + 1)));
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let run = run_on(dir.path(), &server, &["--rolling-context"]);
+        assert_eq!(run.result, Ok(true));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].prompt().contains("Summary 1."));
+        let prompt = requests[1].prompt();
+        assert!(prompt.contains("Summary 1."));
+        assert!(prompt.contains("second"));
+    }
+
+    #[test]
+    fn identical_chunks_share_one_request() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "same text"), ("part_002.txt", "same text")]);
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests().len(), 1);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+        assert_eq!(stored_summary(&results["part_002.txt"]), Some("A summary."));
+    }
+
+    #[test]
+    fn abort_after_failures_stops_after_that_many_in_a_row() {
+        let server = MockServer::start(|index, _| match index {
+            0 => Reply::summary("A summary."),
+            _ => Reply::status(500),
+        });
+        let dir = chunk_dir(&[
+            ("part_001.txt", "one"),
+            ("part_002.txt", "two"),
+            ("part_003.txt", "three"),
+            ("part_004.txt", "four"),
+            ("part_005.txt", "five"),
+        ]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--abort-after-failures",
+                "2",
+                "--retries",
+                "0",
+                "--order-from",
+                "filename",
+            ],
+        );
+        assert_eq!(run.result, Ok(false));
+        assert_eq!(server.requests().len(), 3);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+        assert!(results.get("part_004.txt").is_none());
+
+        let run = run_on(dir.path(), &server, &["--abort-after-failures", "0"]);
+        assert_eq!(
+            run.result,
+            Err("--abort-after-failures must be at least 1".to_string())
+        );
+    }
+
+    #[test]
+    fn unreadable_file_does_not_stop_the_others() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_003.txt", "third")]);
+        // A dangling symlink cannot be read, even by root
+        std::os::unix::fs::symlink(
+            dir.path().join("missing.txt"),
+            dir.path().join("part_002.txt"),
+        )
+        .unwrap();
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(false));
+        assert!(run.stdout.contains("part_002.txt"));
+        assert_eq!(server.requests().len(), 2);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+        assert_eq!(stored_summary(&results["part_003.txt"]), Some("A summary."));
+
+        // --no-keep-going aborts the run, unless a later --keep-going overrides it
+        let run = run_on(dir.path(), &server, &["--no-keep-going"]);
+        assert!(run.result.is_err());
+        let run = run_on(dir.path(), &server, &["--no-keep-going", "--keep-going"]);
+        assert_eq!(run.result, Ok(false));
+    }
+
+    #[test]
+    fn profile_selects_a_preset_from_the_params_file() {
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let params = dir.path().join("params.json");
+        fs::write(
+            &params,
+            r#"{"profiles": {"default": {"temperature": 0.7}, "terse": {"temperature": 0.1}}}"#,
+        )
+        .unwrap();
+        let params = params.to_str().unwrap();
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &["--params", params]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(request_for(&server, "text")["temperature"], json!(0.7));
+
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--params", params, "--profile", "terse"],
+        );
+        assert_eq!(run.result, Ok(true));
+        let request = request_for(&server, "text");
+        assert_eq!(request["temperature"], json!(0.1));
+        assert!(request.get("profiles").is_none());
+    }
+
+    #[test]
+    fn summary_prompt_per_stage_sets_each_reduce_level() {
+        let server =
+            MockServer::start(|index, _| Reply::summary(&format!("Summary {}.", index + 1)));
+        let dir = chunk_dir(&[
+            ("part_001.txt", "one"),
+            ("part_002.txt", "two"),
+            ("part_003.txt", "three"),
+            ("part_004.txt", "four"),
+        ]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--reduce-inline",
+                "--reduce-fanout",
+                "2",
+                "--summary-prompt-per-stage",
+                "Combine the sections:",
+                "--summary-prompt-per-stage",
+                "Write the final summary:",
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts.len(), 7);
+        assert!(prompts[4].starts_with("Combine the sections:\n\n"));
+        assert!(prompts[5].starts_with("Combine the sections:\n\n"));
+        assert!(prompts[6].starts_with("Write the final summary:\n\n"));
+        assert_eq!(results_in(dir.path())[COMBINED_KEY], json!("Summary 7."));
+    }
+
+    #[test]
+    fn output_key_template_renders_the_keys() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("x_part_001.txt", "first"), ("x_part_002.txt", "second")]);
+        let run = run_on(dir.path(), &server, &["--output-key-template", "{index}"]);
+        assert_eq!(run.result, Ok(true));
+        let results = results_in(dir.path());
+        let mut keys: Vec<&String> = results.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["001", "002"]);
+        assert_eq!(stored_summary(&results["001"]), Some("A summary."));
+    }
+
+    #[test]
+    fn since_checkpoint_skips_the_files_completed_before_a_restart() {
+        let dir = chunk_dir(&[("part_001.txt", "one"), ("part_002.txt", "two")]);
+        // The first run is cut short by a failure on the second file
+        let server = MockServer::start(|_, request| match request.prompt().as_str() {
+            "two" => Reply::status(500),
+            _ => Reply::summary("A summary."),
+        });
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--since-checkpoint", "--retries", "0"],
+        );
+        assert_eq!(run.result, Ok(false));
+        let checkpoint = dir.path().join("summaries.json.checkpoint");
+        assert!(fs::read_to_string(&checkpoint)
+            .unwrap()
+            .contains("part_001.txt"));
+
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &["--since-checkpoint"]);
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts, ["two"]);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+        assert_eq!(stored_summary(&results["part_002.txt"]), Some("A summary."));
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn max_prompt_bytes_skips_or_truncates_an_oversized_prompt() {
+        let dir = chunk_dir(&[("part_001.txt", "alpha beta gamma delta")]);
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &["--max-prompt-bytes", "12"]);
+        assert_eq!(run.result, Ok(false));
+        assert!(run
+            .stdout
+            .contains("prompt is 22 bytes, over the --max-prompt-bytes limit of 12; skipped"));
+        assert!(server.requests().is_empty());
+
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--max-prompt-bytes", "12", "--on-oversize", "truncate"],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests()[0].prompt(), "alpha beta");
+    }
+
+    #[test]
+    fn use_context_sends_the_previous_context() {
+        let server = MockServer::start(|index, _| {
+            Reply::json(json!({ "response": "A summary.", "done": true, "context": [index, 7] }))
+        });
+        let dir = chunk_dir(&[("part_001.txt", "one"), ("part_002.txt", "two")]);
+        let run = run_on(dir.path(), &server, &["--use-context"]);
+        assert_eq!(run.result, Ok(true));
+        assert!(request_for(&server, "one").get("context").is_none());
+        assert_eq!(request_for(&server, "two")["context"], json!([0, 7]));
+    }
+
+    #[test]
+    fn params_env_merges_the_variable_into_the_request() {
+        // A variable no other test reads, as the environment is shared by the test threads
+        std::env::set_var("PARAMS_ENV_TEST_PARAMS", r#"{"temperature": 0.3}"#);
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--params-env", "PARAMS_ENV_TEST_PARAMS"],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(request_for(&server, "text")["temperature"], json!(0.3));
+    }
+
+    #[test]
+    fn with_metadata_adds_the_run_id_and_timestamp() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--with-metadata", "--run-id", "run-42"],
+        );
+        assert_eq!(run.result, Ok(true));
+        let results = results_in(dir.path());
+        assert_eq!(results[RUN_ID_KEY], json!("run-42"));
+        assert!(results["_timestamp"].is_string());
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+    }
+
+    #[test]
+    fn zip_archive_entries_are_read_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("chunks.zip");
+        let mut archive = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        for (name, content) in [
+            ("part_002.txt", "second"),
+            ("talk/part_001.txt", "first"),
+            ("notes.md", "not a chunk"),
+        ] {
+            archive
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            archive.write_all(content.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+        let archive_path = archive_path.to_str().unwrap();
+        let output = dir.path().join("summaries.json");
+        let output = output.to_str().unwrap();
+
+        let server = MockServer::start(|_, request| Reply::summary(&request.prompt()));
+        let run = run_against(&server, &["--dir", archive_path, "--output", output]);
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(prompts, ["first", "second"]);
+        let results = results_in(dir.path());
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("first"));
+        assert_eq!(stored_summary(&results["part_002.txt"]), Some("second"));
+
+        let run = run_against(
+            &server,
+            &["--dir", archive_path, "--output", output, "--sidecar"],
+        );
+        assert_eq!(
+            run.result,
+            Err("--sidecar cannot be used with a zip archive".to_string())
+        );
+
+        let manifest = dir.path().join("checksums.sha256");
+        let manifest = manifest.to_str().unwrap();
+        let run = run_against(
+            &server,
+            &[
+                "--dir",
+                archive_path,
+                "--output",
+                output,
+                "--checksum-manifest",
+                manifest,
+            ],
+        );
+        assert_eq!(
+            run.result,
+            Err("--checksum-manifest cannot be used with a zip archive".to_string())
+        );
+    }
+
+    #[test]
+    fn lossy_utf8_replaces_an_invalid_byte_that_strict_reading_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("part_001.txt"), b"caf\xe9 talk").unwrap();
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(false));
+        assert!(server.requests().is_empty());
+
+        let run = run_on(dir.path(), &server, &["--lossy-utf8"]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests()[0].prompt(), "caf\u{fffd} talk");
+    }
+
+    #[test]
+    fn retry_on_status_retries_only_the_listed_statuses() {
+        let dir = chunk_dir(&[("part_001.txt", "text")]);
+        let run_with = |statuses: &str| {
+            let server = MockServer::start(|index, _| match index {
+                0 => Reply::status(503),
+                _ => Reply::summary("A summary."),
+            });
+            let run = run_on(
+                dir.path(),
+                &server,
+                &[
+                    "--retry-on-status",
+                    statuses,
+                    "--retries",
+                    "1",
+                    "--retry-backoff",
+                    "0",
+                ],
+            );
+            (run.result, server.requests().len())
+        };
+        assert_eq!(run_with("429,503"), (Ok(true), 2));
+        assert_eq!(run_with("429,502"), (Ok(false), 1));
+    }
+
+    #[test]
+    fn output_dash_writes_only_the_results_json_to_stdout() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "first"), ("part_002.txt", "second")]);
+        let run = run_against(
+            &server,
+            &["--dir", dir.path().to_str().unwrap(), "--output", "-"],
+        );
+        assert_eq!(run.result, Ok(true));
+        let results: Value = serde_json::from_str(&run.stdout).unwrap();
+        assert_eq!(stored_summary(&results["part_001.txt"]), Some("A summary."));
+        assert_eq!(stored_summary(&results["part_002.txt"]), Some("A summary."));
+        assert!(run.stderr.contains("Processed 2/2 files"));
+    }
+
+    #[test]
+    fn parse_filename_params_sets_the_length_of_marked_files() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "plain"), ("part_002.len200.txt", "marked")]);
+        let params = dir.path().join("params.json");
+        fs::write(&params, r#"{"options": {"num_predict": 80}}"#).unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &[
+                "--params",
+                params.to_str().unwrap(),
+                "--parse-filename-params",
+            ],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(
+            request_for(&server, "plain")["options"]["num_predict"],
+            json!(80)
+        );
+        assert_eq!(
+            request_for(&server, "marked")["options"]["num_predict"],
+            json!(200)
+        );
+    }
+
+    #[test]
+    fn request_timeout_grows_with_the_prompt_tokens() {
+        let body = |tokens: usize| json!({ "prompt": vec!["word"; tokens].join(" ") });
+        assert_eq!(request_timeout(None, &body(1000)), None);
+        assert_eq!(
+            request_timeout(Some(100), &body(1000)),
+            Some(Duration::from_secs(100))
+        );
+        assert_eq!(
+            request_timeout(Some(100), &body(2000)),
+            Some(Duration::from_secs(200))
+        );
+        // Short prompts still get the floor
+        assert_eq!(
+            request_timeout(Some(100), &body(10)),
+            Some(MIN_REQUEST_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn request_template_substitutes_the_chunk_into_a_messages_array() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "chunk text")]);
+        let template = dir.path().join("template.json");
+        fs::write(
+            &template,
+            r#"{"messages": [{"role": "system", "content": "Summarize."}, {"role": "user", "content": "{{text}}"}]}"#,
+        )
+        .unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--request-template", template.to_str().unwrap()],
+        );
+        assert_eq!(run.result, Ok(true));
+        let body: Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+        assert_eq!(
+            body["messages"],
+            json!([
+                { "role": "system", "content": "Summarize." },
+                { "role": "user", "content": "chunk text" }
+            ])
+        );
+        assert!(body.get("prompt").is_none());
+    }
+
+    #[test]
+    fn response_pointer_extracts_openai_style_content() {
+        let server = MockServer::start(|_, _| {
+            Reply::json(json!({
+                "choices": [{ "message": { "role": "assistant", "content": "Chat summary." } }]
+            }))
+        });
+        let dir = chunk_dir(&[("part_001.txt", "chunk text")]);
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--response-pointer", "/choices/0/message/content"],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("Chat summary.")
+        );
+    }
+
+    #[test]
+    fn resume_partial_continues_an_interrupted_stream() {
+        let server = MockServer::start(|index, _| match index {
+            0 => Reply::stream(&["The meeting", " covered the", " budget"]).interrupted_after(2),
+            _ => Reply::stream(&[" and the", " schedule."]),
+        });
+        let dir = chunk_dir(&[("part_001.txt", "chunk text")]);
+        let partial = dir.path().join("part_001.txt.partial");
+
+        // The stream breaks off, keeping what was generated
+        let run = run_on(dir.path(), &server, &["--stream"]);
+        assert_eq!(run.result, Ok(false));
+        assert_eq!(
+            fs::read_to_string(&partial).unwrap(),
+            "The meeting covered the"
+        );
+
+        // The resumed request sends the saved text after the prompt to be continued, and the new tokens are
+        // appended to it
+        let run = run_on(dir.path(), &server, &["--stream", "--resume-partial"]);
+        assert_eq!(run.result, Ok(true));
+        let requests = server.requests();
+        let sent: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(sent["stream"], json!(true));
+        assert_eq!(
+            requests[1].prompt(),
+            format!("{}\n\nThe meeting covered the", requests[0].prompt())
+        );
+        assert_eq!(sent["raw"], json!(true));
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("The meeting covered the and the schedule.")
+        );
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn normalize_unicode_sends_decomposed_chunks_composed() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[("part_001.txt", "Cafe\u{301} re\u{301}sume\u{301}")]);
+        let run = run_on(dir.path(), &server, &["--normalize-unicode", "nfc"]);
+        assert_eq!(run.result, Ok(true));
+        assert_eq!(server.requests()[0].prompt(), "Caf\u{e9} r\u{e9}sum\u{e9}");
+
+        // The planned tokens are counted on the normalized prompt too
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--normalize-unicode", "nfc", "--count-only"],
+        );
+        assert!(run.stdout.contains("part_001.txt: 2 tokens"));
+    }
+
+    #[test]
+    fn count_only_reports_the_total_without_sending_requests() {
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let dir = chunk_dir(&[
+            ("part_001.txt", "one two three"),
+            ("part_002.txt", "four five"),
+        ]);
+        let context_dir = tempfile::tempdir().unwrap();
+        let context = context_dir.path().join("context.txt");
+        fs::write(&context, "Meeting notes").unwrap();
+        let run = run_on(
+            dir.path(),
+            &server,
+            &["--count-only", "--context-file", context.to_str().unwrap()],
+        );
+        assert_eq!(run.result, Ok(true));
+        assert!(server.requests().is_empty());
+        // Each prompt counts the two context words as well as its chunk's
+        assert!(run.stdout.contains("part_001.txt: 5 tokens"));
+        assert!(run.stdout.contains("part_002.txt: 4 tokens"));
+        assert!(run.stdout.contains("Total: 9 prompt tokens in 2 requests"));
+        assert!(!dir.path().join("summaries.json").exists());
+    }
+
+    #[test]
+    fn shrink_on_overflow_retries_an_oversized_prompt_shorter() {
+        // The mock model fits at most 6 words of prompt
+        let server = MockServer::start(|_, request| {
+            if request.prompt().split_whitespace().count() > 6 {
+                Reply {
+                    status: 400,
+                    headers: Vec::new(),
+                    body: json!({ "error": "Prompt exceeds the context length" }).to_string(),
+                    cut_after: None,
+                }
+            } else {
+                Reply::summary("A summary.")
+            }
+        });
+        let dir = chunk_dir(&[("part_001.txt", "one two three four five six seven eight")]);
+        let run = run_on(dir.path(), &server, &["--shrink-on-overflow", "25"]);
+        assert_eq!(run.result, Ok(true));
+        let prompts: Vec<String> = server.requests().iter().map(Received::prompt).collect();
+        assert_eq!(
+            prompts,
+            [
+                "one two three four five six seven eight",
+                "one two three four five six"
+            ]
+        );
+        assert_eq!(
+            stored_summary(&results_in(dir.path())["part_001.txt"]),
+            Some("A summary.")
+        );
+
+        // Without the option the chunk fails
+        let run = run_on(dir.path(), &server, &[]);
+        assert_eq!(run.result, Ok(false));
+    }
+
+    #[test]
+    fn reduce_inline_stores_the_combined_summary_with_the_chunk_entries() {
+        let server = MockServer::start(|index, _| match index {
+            0 => Reply::summary("First summary."),
+            1 => Reply::summary("Second summary."),
+            _ => Reply::summary("Combined summary."),
+        });
+        let dir = chunk_dir(&[
+            ("part_001.txt", "first chunk"),
+            ("part_002.txt", "second chunk"),
+        ]);
+        let run = run_on(dir.path(), &server, &["--reduce-inline"]);
+        assert_eq!(run.result, Ok(true));
+        assert!(server.requests()[2]
+            .prompt()
+            .ends_with("First summary.\n\nSecond summary."));
+        let results = results_in(dir.path());
+        assert_eq!(
+            stored_summary(&results["part_001.txt"]),
+            Some("First summary.")
+        );
+        assert_eq!(
+            stored_summary(&results["part_002.txt"]),
+            Some("Second summary.")
+        );
+        assert_eq!(results[COMBINED_KEY], json!("Combined summary."));
+    }
+
+    #[test]
+    fn params_are_validated_up_front_and_read_once() {
+        let params_dir = tempfile::tempdir().unwrap();
+        let params = params_dir.path().join("params.json");
+        let dir = chunk_dir(&[
+            ("part_001.txt", "first chunk"),
+            ("part_002.txt", "second chunk"),
+        ]);
+
+        // A syntax error fails the run with its position before any request
+        fs::write(&params, "{\"temperature\": 0.2,}").unwrap();
+        let server = MockServer::start(|_, _| Reply::summary("A summary."));
+        let run = run_on(dir.path(), &server, &["--params", params.to_str().unwrap()]);
+        let error = run.result.unwrap_err();
+        assert!(
+            error.starts_with("Invalid JSON in params file"),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("trailing comma at line 1 column 21"),
+            "{}",
+            error
+        );
+        assert!(server.requests().is_empty());
+
+        // Breaking the file after the first request does not affect the rest of the run
+        fs::write(&params, "{\"temperature\": 0.2}").unwrap();
+        let overwritten = params.clone();
+        let server = MockServer::start(move |_, _| {
+            fs::write(&overwritten, "{ broken").unwrap();
+            Reply::summary("A summary.")
+        });
+        let run = run_on(dir.path(), &server, &["--params", params.to_str().unwrap()]);
+        assert_eq!(run.result, Ok(true));
+        let temperatures: Vec<Value> = server
+            .requests()
+            .iter()
+            .map(|request| {
+                serde_json::from_str::<Value>(&request.body).unwrap()["temperature"].clone()
+            })
+            .collect();
+        assert_eq!(temperatures, [json!(0.2), json!(0.2)]);
+    }
+}
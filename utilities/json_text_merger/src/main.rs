@@ -6,11 +6,16 @@
 // from the JSON, sorts them based on numeric values in their filenames, and merges them into a single text file.
 // The tool supports two modes of JSON file handling: "koboldai" mode and "ollama" mode. In "koboldai" mode, the tool
 // expects the JSON file structure to have entries with a "results" field, which contains an array of objects,
-// each having a "text" field containing the actual text to be merged. In "ollama" mode, the tool expects the JSON file
-// structure to be a key-value pair where the keys represent filenames and the values represent the text content.
+// each having a "text" field containing the actual text to be merged (entries written with the summarization
+// CLI's --keep-stats flag carry the "text" field directly). In "ollama" mode, the tool expects the JSON file
+// structure to be a key-value pair where the keys represent filenames and the values represent the text content
+// (or objects with a "response" field, as written with the ollama CLI's --keep-done-reason flag).
 //
 // Dependencies:
 // - serde_json: For serializing and deserializing JSON data.
+// - csv: For writing --format csv output.
+// - glob: For matching filename patterns in --weights.
+// - uuid, humantime: For the --with-metadata run ID and timestamp.
 //
 // How to Use:
 // 1. Compile the code using the Rust compiler.
@@ -18,91 +23,867 @@
 //    - The first argument: Path to the input JSON file.
 //    - The second argument: Path to the output text file.
 //    - The third argument: Mode of JSON file handling. Options are "koboldai" or "ollama".
+//    - --flatten [--flatten-key <key>]: Optional. In "koboldai" mode, collect every string under a "text" key (or
+//      <key>) at any depth of each entry, in document order, instead of only the "text" fields of "results".
+//    - --continue-on-parse-error: Optional. In "ollama" mode, skip entries whose value is not a string
+//      (reporting them on stderr) instead of aborting the merge.
+//    - --dedupe-overlap: Optional. Remove text repeated at the boundary of consecutive entries (for example
+//      when the splitter was run with an overlap) so it appears only once in the merged output.
+//    - --dedupe-sentences: Optional. Remove sentences repeated anywhere in the merged document, keeping the first
+//      occurrence. Sentences match when they have the same words, ignoring case and punctuation.
+//    - --sort <numeric|alpha|length|none>: Optional. Order of the merged entries: by filename in natural order,
+//      comparing the numbers in it by value so part2 comes before part10 (default), alphabetically by filename, by text length, or in the order they appear in the JSON file.
+//    - --order-from frontmatter --chunks-dir <dir>: Optional. Order the entries by the "order:" key in the
+//      ---delimited front-matter of their chunk files in <dir>, taking precedence over --sort. Entries whose chunk
+//      file has no front-matter fall back to the last number in their filename.
+//    - --format <text|csv|outline>: Optional. Write the merged entries as text (default), as CSV with a
+//      "filename,text" header and one row per entry, in the same order, or as a markdown outline with a heading
+//      bullet per entry and the lines of its summary as bullets nested under it, keeping their relative nesting.
+//    - --number-sections: Optional. In text output, number the merged entries 1., 2., 3., ... in the order they
+//      are merged, independent of the numbers in their filenames (--prefix-numbers is an alias).
+//    - --with-timing <dir>: Optional. Precede each merged entry with a [start–end] marker read from the
+//      "Start Time:" and "End Time:" lines of the timed chunk file of the same name in <dir>, e.g. the
+//      subtitle converter's output, so summaries can be correlated back to time ranges.
+//    - --source-dir <dir>: Optional. Follow each entry's original chunk file of the same name in <dir> with its
+//      summary, as "ORIGINAL:" and "SUMMARY:" blocks, to proofread the summaries against their sources.
+//    - --weights <json_file>: Optional. Emphasize entries matching filename glob patterns, e.g.
+//      { "*_part_003.txt": 2, "*_part_007.txt": "IMPORTANT:" }: a number repeats a matching entry that many times
+//      (0 leaves it out) and a string prefixes it as a marker. The first pattern in the file matching an entry
+//      applies.
+//    - --with-metadata: Optional. Stamp the output with a run ID and timestamp: text output starts with
+//      "_run_id: <id>" and "_timestamp: <time>" lines, CSV output gains "_run_id" and "_timestamp" columns and
+//      --combine-json output gains top-level "_run_id" and "_timestamp" keys. The "_run_id" and "_timestamp" keys
+//      of input files (written by the summarization CLIs' --with-metadata) are never merged as entries.
+//    - --run-id <id>: Optional. Run ID written with --with-metadata, e.g. the one given to the summarization
+//      CLI, so the outputs of a pipeline can be correlated. Defaults to a random UUID.
+//
+// Combining outputs:
+// With --combine-json, the tool instead merges several summarization output JSON files into one:
+//    $ ./json_text_merger --combine-json <output_json> <json_file>... [--overwrite-keys]
+// Entries from every input are collected into a single filename -> summary object. A filename present in more
+// than one input with a different value is an error unless --overwrite-keys is given, in which case the
+// entry from the last input wins.
+//
+// Comparing outputs:
+// With --compare, the tool instead reports how the summaries of two summarization output JSON files differ:
+//    $ ./json_text_merger --compare <old_json> <new_json> <mode> [--diff]
+// Every filename is reported as identical, changed, added (only in the new output) or removed (only in the old
+// output), followed by the count of each. With --diff, a unified diff of the summary lines follows each changed
+// entry.
 //
 // Example Usage:
 // $ ./json_text_merger input.json output.txt koboldai
+// $ ./json_text_merger input.json output.txt ollama --continue-on-parse-error
 //
 // This tool provides a convenient way to merge text entries from JSON files, allowing for easy manipulation and
 // processing of structured text data.
 //
 
 
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use serde_json::Value;
+use std::path::Path;
+use std::time::SystemTime;
+use serde_json::{Map, Value};
 use serde::ser::Error;
+use glob::Pattern;
+
+// Minimum number of words a boundary overlap must span before it is removed,
+// so a single shared word like "the" is not treated as duplicated text
+const MIN_OVERLAP_WORDS: usize = 3;
+
+// Keys of the --with-metadata run ID and timestamp, shared with the summarization CLIs' outputs
+const RUN_ID_KEY: &str = "_run_id";
+const RUN_TIMESTAMP_KEY: &str = "_timestamp";
+// Key of the combined summary written by the summarization CLIs' --reduce-inline
+const COMBINED_KEY: &str = "_combined";
 
 enum JsonMode {
     Koboldai,
     Ollama,
 }
 
+// Format of the merged output file
+enum OutputFormat {
+    Text,
+    Csv,
+    Outline,
+}
+
+// Emphasis given to the entries matching a --weights pattern
+enum Weight {
+    Repeat(usize),
+    Prefix(String),
+}
+
+// Order in which the extracted entries are merged
+enum SortStrategy {
+    Numeric,
+    Alpha,
+    Length,
+    None,
+}
+
 fn main() -> io::Result<()> {
-    // Get command-line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 4 {
-        eprintln!("Usage: {} <json_file> <output_file> <mode>", args[0]);
+    run(env::args())
+}
+
+// Merge, combine or compare JSON files as the command-line arguments say, the program name first
+fn run(mut args: impl Iterator<Item = String>) -> io::Result<()> {
+    let program = args.next().unwrap_or_default();
+    let mut positional = Vec::new();
+    let mut continue_on_parse_error = false;
+    let mut flatten = false;
+    let mut flatten_key = None;
+    let mut dedupe_overlap = false;
+    let mut dedupe_sentences = false;
+    let mut sort = SortStrategy::Numeric;
+    let mut combine_json = false;
+    let mut compare = false;
+    let mut show_diff = false;
+    let mut overwrite_keys = false;
+    let mut order_from_frontmatter = false;
+    let mut chunks_dir = None;
+    let mut timing_dir = None;
+    let mut source_dir = None;
+    let mut format = OutputFormat::Text;
+    let mut number_sections = false;
+    let mut with_metadata = false;
+    let mut weights_file = None;
+    let mut run_id = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--continue-on-parse-error" => continue_on_parse_error = true,
+            "--dedupe-overlap" => dedupe_overlap = true,
+            "--flatten" => flatten = true,
+            "--flatten-key" => {
+                let Some(key) = args.next() else {
+                    eprintln!("Expected a key name after --flatten-key");
+                    return Ok(());
+                };
+                flatten_key = Some(key);
+            }
+            "--dedupe-sentences" => dedupe_sentences = true,
+            "--combine-json" => combine_json = true,
+            "--compare" => compare = true,
+            "--diff" => show_diff = true,
+            "--overwrite-keys" => overwrite_keys = true,
+            "--number-sections" | "--prefix-numbers" => number_sections = true,
+            "--with-metadata" => with_metadata = true,
+            "--weights" => {
+                let Some(file) = args.next() else {
+                    eprintln!("Expected a JSON file of weights after --weights");
+                    return Ok(());
+                };
+                weights_file = Some(file);
+            }
+            "--run-id" => {
+                let Some(id) = args.next() else {
+                    eprintln!("Expected a run ID after --run-id");
+                    return Ok(());
+                };
+                run_id = Some(id);
+            }
+            "--sort" => {
+                sort = match args.next().as_deref() {
+                    Some("numeric") => SortStrategy::Numeric,
+                    Some("alpha") => SortStrategy::Alpha,
+                    Some("length") => SortStrategy::Length,
+                    Some("none") => SortStrategy::None,
+                    _ => {
+                        eprintln!("Invalid sort strategy. Supported strategies: numeric, alpha, length, none");
+                        return Ok(());
+                    }
+                };
+            }
+            "--order-from" => {
+                if args.next().as_deref() != Some("frontmatter") {
+                    eprintln!("Invalid order source. Supported sources: frontmatter");
+                    return Ok(());
+                }
+                order_from_frontmatter = true;
+            }
+            "--chunks-dir" => {
+                let Some(dir) = args.next() else {
+                    eprintln!("Expected a directory after --chunks-dir");
+                    return Ok(());
+                };
+                chunks_dir = Some(dir);
+            }
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("csv") => OutputFormat::Csv,
+                    Some("outline") => OutputFormat::Outline,
+                    _ => {
+                        eprintln!("Invalid output format. Supported formats: text, csv, outline");
+                        return Ok(());
+                    }
+                };
+            }
+            "--source-dir" => {
+                let Some(dir) = args.next() else {
+                    eprintln!("Expected a directory of original chunks after --source-dir");
+                    return Ok(());
+                };
+                source_dir = Some(dir);
+            }
+            "--with-timing" => {
+                let Some(dir) = args.next() else {
+                    eprintln!("Expected a directory of timed chunks after --with-timing");
+                    return Ok(());
+                };
+                timing_dir = Some(dir);
+            }
+            flag if flag.starts_with("--") => {
+                eprintln!("Invalid flag '{}'", flag);
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if flatten_key.is_some() && !flatten {
+        eprintln!("--flatten-key requires --flatten");
+        return Ok(());
+    }
+    // The key whose strings --flatten collects
+    let flatten_key = flatten.then(|| flatten_key.unwrap_or_else(|| "text".to_string()));
+
+    if show_diff && !compare {
+        eprintln!("--diff requires --compare");
         return Ok(());
     }
-    let json_file = &args[1];
-    let output_file = &args[2];
-    let mode = match &args[3][..] {
-        "koboldai" => JsonMode::Koboldai,
-        "ollama" => JsonMode::Ollama,
-        _ => {
+
+    if run_id.is_some() && !with_metadata {
+        eprintln!("--run-id requires --with-metadata");
+        return Ok(());
+    }
+    let metadata = if with_metadata { run_metadata(run_id) } else { Vec::new() };
+
+    if combine_json {
+        if positional.len() < 2 {
+            eprintln!(
+                "Usage: {} --combine-json <output_json> <json_file>... [--overwrite-keys] [--with-metadata [--run-id <id>]]",
+                program
+            );
+            return Ok(());
+        }
+        let mut combined = combine_json_files(&positional[1..], overwrite_keys)?;
+        for (key, value) in &metadata {
+            combined.insert(key.to_string(), Value::from(value.as_str()));
+        }
+        let output_file = File::create(&positional[0])?;
+        serde_json::to_writer_pretty(output_file, &combined)?;
+        return Ok(());
+    }
+
+    if compare {
+        if positional.len() != 3 {
+            eprintln!("Usage: {} --compare <old_json> <new_json> <mode> [--diff]", program);
+            return Ok(());
+        }
+        let Some(mode) = parse_mode(&positional[2]) else {
             eprintln!("Invalid mode. Supported modes: koboldai, ollama");
             return Ok(());
+        };
+        if flatten && matches!(mode, JsonMode::Ollama) {
+            eprintln!("--flatten is only supported in koboldai mode");
+            return Ok(());
         }
+        let flatten_key = flatten_key.as_deref();
+        let old_entries = read_entries(&positional[0], &mode, continue_on_parse_error, flatten_key)?;
+        let new_entries = read_entries(&positional[1], &mode, continue_on_parse_error, flatten_key)?;
+        print!("{}", compare_outputs(&old_entries, &new_entries, show_diff));
+        return Ok(());
+    }
+
+    if positional.len() != 3 {
+        eprintln!(
+            "Usage: {} <json_file> <output_file> <mode> [--flatten [--flatten-key <key>]] [--continue-on-parse-error] [--dedupe-overlap] [--dedupe-sentences] [--sort <numeric|alpha|length|none>] [--order-from frontmatter --chunks-dir <dir>] [--with-timing <dir>] [--source-dir <dir>] [--format <text|csv|outline>] [--number-sections] [--weights <json_file>] [--with-metadata [--run-id <id>]]",
+            program
+        );
+        return Ok(());
+    }
+    let json_file = &positional[0];
+    let output_file = &positional[1];
+    let Some(mode) = parse_mode(&positional[2]) else {
+        eprintln!("Invalid mode. Supported modes: koboldai, ollama");
+        return Ok(());
     };
+    if flatten && matches!(mode, JsonMode::Ollama) {
+        eprintln!("--flatten is only supported in koboldai mode");
+        return Ok(());
+    }
+
+    if order_from_frontmatter && chunks_dir.is_none() {
+        eprintln!("--order-from frontmatter requires --chunks-dir");
+        return Ok(());
+    }
 
     // Read the JSON file
-    let mut file = File::open(json_file)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    let contents = read_json_file(json_file)?;
 
     // Parse JSON based on mode
     let texts = match mode {
-        JsonMode::Koboldai => parse_koboldai_json(&contents),
-        JsonMode::Ollama => parse_ollama_json(&contents),
+        JsonMode::Koboldai => parse_koboldai_json(&contents, flatten_key.as_deref()),
+        JsonMode::Ollama => parse_ollama_json(&contents, continue_on_parse_error),
     }?;
 
-    // Sort texts based on the file name numbers
+    // Sort texts with the chosen strategy; texts are extracted in the order of the JSON file
     let mut sorted_texts = texts.iter().collect::<Vec<_>>();
-    sorted_texts.sort_by_key(|&(filename, _)| {
-        filename
-            .split('_')
-            .nth(2)
-            .unwrap_or("0")
-            .parse::<usize>()
-            .unwrap_or(0)
-    });
+    match sort {
+        SortStrategy::Numeric => sorted_texts.sort_by(|(a, _), (b, _)| natural_cmp(a, b)),
+        SortStrategy::Alpha => sorted_texts.sort_by_key(|&(filename, _)| filename),
+        SortStrategy::Length => sorted_texts.sort_by_key(|&(_, text)| text.chars().count()),
+        SortStrategy::None => {}
+    }
+
+    // The chunk files' front-matter order takes precedence over the sort strategy
+    if let Some(chunks_dir) = chunks_dir.filter(|_| order_from_frontmatter) {
+        sorted_texts.sort_by_cached_key(|&(filename, _)| {
+            let order = fs::read_to_string(Path::new(&chunks_dir).join(filename))
+                .ok()
+                .and_then(|chunk| front_matter_order(chunk.strip_prefix('\u{feff}').unwrap_or(&chunk)));
+            (order.unwrap_or(filename_number(filename) as i64), filename)
+        });
+    }
 
     // Merge texts into a single string
-    let merged_text = sorted_texts
+    let mut merged_texts = sorted_texts
     .iter()
-    .map(|&(_, ref text)| text.clone())
-    .collect::<Vec<_>>()
-    .join("\n");
+    .map(|(_, text)| text.clone())
+    .collect::<Vec<_>>();
+    if dedupe_overlap {
+        remove_boundary_overlaps(&mut merged_texts);
+    }
+    if dedupe_sentences {
+        remove_duplicate_sentences(&mut merged_texts);
+    }
+
+    // Pair each summary with the original chunk it was made from
+    if let Some(source_dir) = &source_dir {
+        for ((filename, _), text) in sorted_texts.iter().zip(merged_texts.iter_mut()) {
+            match fs::read_to_string(Path::new(source_dir).join(filename)) {
+                Ok(source) => {
+                    let source = source.strip_prefix('\u{feff}').unwrap_or(&source).trim();
+                    *text = format!("ORIGINAL:\n{}\nSUMMARY:\n{}\n", source, text);
+                }
+                Err(e) => eprintln!("No original chunk for {} in {}: {}", filename, source_dir, e),
+            }
+        }
+    }
 
-    // Write merged text to the output file
-    let mut output_file = File::create(output_file)?;
-    output_file.write_all(merged_text.as_bytes())?;
+    // Mark each segment with the time range of its timed chunk
+    if let Some(timing_dir) = &timing_dir {
+        for ((filename, _), text) in sorted_texts.iter().zip(merged_texts.iter_mut()) {
+            match chunk_time_range(&Path::new(timing_dir).join(filename)) {
+                Some((start_ms, end_ms)) => {
+                    *text = format!("[{}–{}]\n{}", format_timestamp(start_ms), format_timestamp(end_ms), text);
+                }
+                None => eprintln!("No timing found for {} in {}", filename, timing_dir),
+            }
+        }
+    }
+
+    // Emphasize weighted entries by repeating them or marking them
+    if let Some(weights_file) = &weights_file {
+        let weights = read_weights(weights_file)?;
+        let mut weighted_entries = Vec::new();
+        let mut weighted_texts = Vec::new();
+        for (entry, text) in sorted_texts.into_iter().zip(merged_texts) {
+            match weights.iter().find(|(pattern, _)| pattern.matches(&entry.0)) {
+                Some((_, Weight::Repeat(count))) => {
+                    for _ in 0..*count {
+                        weighted_entries.push(entry);
+                        weighted_texts.push(text.clone());
+                    }
+                }
+                Some((_, Weight::Prefix(marker))) => {
+                    weighted_entries.push(entry);
+                    weighted_texts.push(format!("{} {}", marker, text));
+                }
+                None => {
+                    weighted_entries.push(entry);
+                    weighted_texts.push(text);
+                }
+            }
+        }
+        sorted_texts = weighted_entries;
+        merged_texts = weighted_texts;
+    }
+
+    match format {
+        OutputFormat::Text => {
+            // Number the sections in merge order
+            if number_sections {
+                for (i, text) in merged_texts.iter_mut().enumerate() {
+                    *text = format!("{}. {}", i + 1, text);
+                }
+            }
+
+            // The run metadata heads the merged text, one "key: value" line each
+            let header: String = metadata.iter().map(|(key, value)| format!("{}: {}\n", key, value)).collect();
+            let merged_text = if header.is_empty() {
+                merged_texts.join("\n")
+            } else {
+                format!("{}\n{}", header, merged_texts.join("\n"))
+            };
+
+            // Write merged text to the output file
+            let mut output_file = File::create(output_file)?;
+            output_file.write_all(merged_text.as_bytes())?;
+        }
+        OutputFormat::Csv => {
+            // One row per entry; the csv writer quotes texts containing commas, quotes or newlines
+            let mut writer = csv::Writer::from_path(output_file)?;
+            // The run metadata is repeated in extra columns of every row
+            let mut header = vec!["filename", "text"];
+            header.extend(metadata.iter().map(|(key, _)| *key));
+            writer.write_record(&header)?;
+            for ((filename, _), text) in sorted_texts.iter().zip(&merged_texts) {
+                let mut record = vec![filename.as_str(), text.as_str()];
+                record.extend(metadata.iter().map(|(_, value)| value.as_str()));
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Outline => {
+            // One top-level bullet per entry, headed like text output by the run metadata
+            let outline: String = sorted_texts
+                .iter()
+                .zip(&merged_texts)
+                .map(|((filename, _), text)| outline_section(filename, text))
+                .collect();
+            let header: String = metadata.iter().map(|(key, value)| format!("{}: {}\n", key, value)).collect();
+            let outline = if header.is_empty() { outline } else { format!("{}\n{}", header, outline) };
+            let mut output_file = File::create(output_file)?;
+            output_file.write_all(outline.as_bytes())?;
+        }
+    }
 
     Ok(())
 }
 
-fn parse_koboldai_json(contents: &str) -> Result<Vec<(String, String)>, serde_json::Error> {
-    let json: BTreeMap<String, Value> = serde_json::from_str(&contents)?;
+// Width of one nesting level in --format outline output
+const OUTLINE_INDENT: &str = "  ";
+
+// An entry as a heading bullet named after its file, with each line of its text as a bullet nested under it.
+// Existing bullets ("-", "*", "+", "•" or "1." / "1)") are re-marked with "-" and keep their relative nesting,
+// one level per distinct indentation; other lines become bullets of the first level.
+fn outline_section(filename: &str, text: &str) -> String {
+    let heading = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let content = line.trim_start();
+            let indent = line[..line.len() - content.len()].chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+            (indent, strip_bullet(content).unwrap_or(content).trim_end())
+        })
+        .collect();
+    let mut indents: Vec<usize> = lines.iter().map(|&(indent, _)| indent).collect();
+    indents.sort_unstable();
+    indents.dedup();
+
+    let mut section = format!("- {}\n", heading);
+    for (indent, content) in lines {
+        let level = indents.binary_search(&indent).unwrap_or(0) + 1;
+        section.push_str(&format!("{}- {}\n", OUTLINE_INDENT.repeat(level), content));
+    }
+    section
+}
+
+// The text of a bullet or numbered list line without its marker, or None for other lines
+fn strip_bullet(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix(['-', '*', '+', '•']) {
+        return rest.strip_prefix(char::is_whitespace).map(str::trim_start);
+    }
+    let digits_end = line.find(|c: char| !c.is_ascii_digit())?;
+    if digits_end == 0 {
+        return None;
+    }
+    line[digits_end..]
+        .strip_prefix(['.', ')'])?
+        .strip_prefix(char::is_whitespace)
+        .map(str::trim_start)
+}
+
+// The --with-metadata run ID, or a random UUID, and the current time, keyed like the summarization CLIs' output
+fn run_metadata(run_id: Option<String>) -> Vec<(&'static str, String)> {
+    let run_id = run_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    vec![(RUN_ID_KEY, run_id), (RUN_TIMESTAMP_KEY, timestamp)]
+}
+
+// Read a --weights file: a JSON object mapping filename glob patterns to a repeat count or a marker prefix,
+// kept in file order
+fn read_weights(weights_file: &str) -> io::Result<Vec<(Pattern, Weight)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let json: Map<String, Value> = serde_json::from_str(&read_json_file(weights_file)?)?;
+    json.into_iter()
+        .map(|(pattern, weight)| {
+            let weight = match weight {
+                Value::Number(count) => count.as_u64().map(|count| Weight::Repeat(count as usize)),
+                Value::String(marker) => Some(Weight::Prefix(marker)),
+                _ => None,
+            }
+            .ok_or_else(|| invalid(format!("Invalid weight for {}: expected a repeat count or a marker", pattern)))?;
+            let pattern = Pattern::new(&pattern).map_err(|e| invalid(format!("Invalid pattern {}: {}", pattern, e)))?;
+            Ok((pattern, weight))
+        })
+        .collect()
+}
+
+// Whether a top-level key of a summarization output holds run metadata or the combined summary rather than an entry
+fn is_metadata_key(key: &str) -> bool {
+    key == RUN_ID_KEY || key == RUN_TIMESTAMP_KEY || key == COMBINED_KEY
+}
+
+// The last number in an entry's filename before the extension, used for entries without a front-matter order,
+// e.g. 3 for transcript_part_003.txt and 10 for ep1_part10.txt, or 0 without one
+fn filename_number(filename: &str) -> usize {
+    let stem = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+    stem.rsplit(|c: char| !c.is_ascii_digit())
+        .find(|digits| !digits.is_empty())
+        .and_then(|digits| digits.parse().ok())
+        .unwrap_or(0)
+}
+
+// Compare filenames in natural order: runs of digits compare by their value, so ep1_part2 comes before
+// ep1_part10, and everything else character by character. Names differing only in zero padding fall back to
+// a plain comparison so the order is total.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let (a_char, b_char) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(&a_char), Some(&b_char)) => (a_char, b_char),
+        };
+        if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let take_digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                digits
+            };
+            let (a_digits, b_digits) = (take_digits(&mut a_chars), take_digits(&mut b_chars));
+            // Without leading zeros, a longer run of digits is a larger number
+            let (a_digits, b_digits) = (a_digits.trim_start_matches('0'), b_digits.trim_start_matches('0'));
+            let ordering = a_digits.len().cmp(&b_digits.len()).then_with(|| a_digits.cmp(b_digits));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else if a_char != b_char {
+            return a_char.cmp(&b_char);
+        } else {
+            a_chars.next();
+            b_chars.next();
+        }
+    }
+}
+
+// The integer "order:" key of a leading ---delimited front-matter block
+fn front_matter_order(text: &str) -> Option<i64> {
+    let mut lines = text.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    let mut order = None;
+    for line in lines {
+        if line.trim_end() == "---" {
+            return order;
+        }
+        if let Some(value) = line.strip_prefix("order:") {
+            order = order.or(value.trim().parse().ok());
+        }
+    }
+    // An unterminated block is not front-matter
+    None
+}
+
+// The first "Start Time:" and last "End Time:" values, in milliseconds, of a timed chunk file
+fn chunk_time_range(chunk_path: &Path) -> Option<(u64, u64)> {
+    let content = fs::read_to_string(chunk_path).ok()?;
+    let content = content.strip_prefix('\u{feff}').unwrap_or(&content);
+    let time_values = |label: &'static str| {
+        content
+            .lines()
+            .filter_map(move |line| line.trim().strip_prefix(label))
+            .filter_map(|value| value.trim().parse::<u64>().ok())
+    };
+    let start_ms = time_values("Start Time:").next()?;
+    let end_ms = time_values("End Time:").next_back()?;
+    Some((start_ms, end_ms))
+}
+
+// Format milliseconds as HH:MM:SS
+fn format_timestamp(ms: u64) -> String {
+    let seconds = ms / 1000;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn parse_mode(mode: &str) -> Option<JsonMode> {
+    match mode {
+        "koboldai" => Some(JsonMode::Koboldai),
+        "ollama" => Some(JsonMode::Ollama),
+        _ => None,
+    }
+}
+
+// The summary of every filename in a summarization output, joining the texts of a filename with several results
+fn read_entries(
+    json_file: &str,
+    mode: &JsonMode,
+    continue_on_parse_error: bool,
+    flatten_key: Option<&str>,
+) -> io::Result<HashMap<String, String>> {
+    let contents = read_json_file(json_file)?;
+    let texts = match mode {
+        JsonMode::Koboldai => parse_koboldai_json(&contents, flatten_key),
+        JsonMode::Ollama => parse_ollama_json(&contents, continue_on_parse_error),
+    }?;
+    let mut entries: HashMap<String, String> = HashMap::new();
+    for (filename, text) in texts {
+        entries
+            .entry(filename)
+            .and_modify(|existing| {
+                existing.push('\n');
+                existing.push_str(&text);
+            })
+            .or_insert(text);
+    }
+    Ok(entries)
+}
+
+// Report each filename of two outputs as identical, changed, added or removed, in natural filename order,
+// followed by the counts of each
+fn compare_outputs(
+    old_entries: &HashMap<String, String>,
+    new_entries: &HashMap<String, String>,
+    show_diff: bool,
+) -> String {
+    let filenames: HashSet<&String> = old_entries.keys().chain(new_entries.keys()).collect();
+    let mut filenames: Vec<&String> = filenames.into_iter().collect();
+    filenames.sort_by(|a, b| natural_cmp(a, b));
+
+    let mut report = String::new();
+    let (mut identical, mut changed, mut added, mut removed) = (0, 0, 0, 0);
+    for filename in filenames {
+        match (old_entries.get(filename), new_entries.get(filename)) {
+            (Some(old_text), Some(new_text)) if old_text == new_text => {
+                identical += 1;
+                report.push_str(&format!("identical  {}\n", filename));
+            }
+            (Some(old_text), Some(new_text)) => {
+                changed += 1;
+                report.push_str(&format!("changed    {}\n", filename));
+                if show_diff {
+                    report.push_str(&unified_diff(filename, old_text, new_text));
+                }
+            }
+            (None, Some(_)) => {
+                added += 1;
+                report.push_str(&format!("added      {}\n", filename));
+            }
+            (Some(_), None) => {
+                removed += 1;
+                report.push_str(&format!("removed    {}\n", filename));
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    report.push_str(&format!("{} identical, {} changed, {} added, {} removed\n", identical, changed, added, removed));
+    report
+}
+
+// A unified diff of the lines of two texts, as a single hunk covering both texts
+fn unified_diff(filename: &str, old_text: &str, new_text: &str) -> String {
+    let old_lines: Vec<&str> = old_text.lines().collect();
+    let new_lines: Vec<&str> = new_text.lines().collect();
+
+    // Longest common subsequence lengths of every pair of suffixes
+    let mut lcs = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            lcs[i][j] = if old_lines[i] == new_lines[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = format!(
+        "--- old/{}\n+++ new/{}\n@@ -1,{} +1,{} @@\n",
+        filename,
+        filename,
+        old_lines.len(),
+        new_lines.len()
+    );
+    let (mut i, mut j) = (0, 0);
+    while i < old_lines.len() || j < new_lines.len() {
+        if i < old_lines.len() && j < new_lines.len() && old_lines[i] == new_lines[j] {
+            diff.push_str(&format!(" {}\n", old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if j == new_lines.len() || (i < old_lines.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push_str(&format!("-{}\n", old_lines[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", new_lines[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+fn read_json_file(json_file: &str) -> io::Result<String> {
+    let mut file = File::open(json_file)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    // Drop a leading UTF-8 byte order mark, which serde_json rejects
+    if let Some(stripped) = contents.strip_prefix('\u{feff}') {
+        contents = stripped.to_string();
+    }
+    Ok(contents)
+}
+
+// Merge the filename -> summary maps of several summarization outputs, in input order
+fn combine_json_files(json_files: &[String], overwrite_keys: bool) -> io::Result<Map<String, Value>> {
+    let mut combined = Map::new();
+    for json_file in json_files {
+        let json: Map<String, Value> = serde_json::from_str(&read_json_file(json_file)?)?;
+        for (filename, value) in json {
+            if is_metadata_key(&filename) {
+                continue;
+            }
+            match combined.get(&filename) {
+                Some(existing) if *existing != value && !overwrite_keys => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Conflicting entry for {} in {} (use --overwrite-keys to keep the last one)",
+                            filename, json_file
+                        ),
+                    ));
+                }
+                _ => {
+                    combined.insert(filename, value);
+                }
+            }
+        }
+    }
+    Ok(combined)
+}
+
+// Drop the head of each text that repeats the tail of the previous text
+fn remove_boundary_overlaps(texts: &mut [String]) {
+    for i in 1..texts.len() {
+        let overlap = boundary_overlap(&texts[i - 1], &texts[i]);
+        if overlap > 0 {
+            let rest = skip_words(&texts[i], overlap).trim_start().to_string();
+            texts[i] = rest;
+        }
+    }
+}
+
+// Drop every sentence already seen earlier in the texts, comparing sentences by their normalized words
+fn remove_duplicate_sentences(texts: &mut [String]) {
+    let mut seen = HashSet::new();
+    for text in texts.iter_mut() {
+        let kept: String = split_sentences(text)
+            .into_iter()
+            .filter(|sentence| {
+                let normalized = normalize_sentence(sentence);
+                // Text without words, such as a lone "...", is never a duplicate
+                normalized.is_empty() || seen.insert(normalized)
+            })
+            .collect();
+        *text = kept.trim_end().to_string();
+    }
+}
+
+// Split text into sentences ending at ".", "!" or "?" followed by whitespace, each keeping its trailing whitespace
+// so the kept sentences can be joined back with the original formatting
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if matches!(c, '.' | '!' | '?') && chars.peek().is_none_or(|&(_, next)| next.is_whitespace()) {
+            while chars.next_if(|&(_, next)| next.is_whitespace()).is_some() {}
+            let end = chars.peek().map_or(text.len(), |&(i, _)| i);
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+// The lowercase words of a sentence without surrounding punctuation, joined by single spaces
+fn normalize_sentence(sentence: &str) -> String {
+    sentence
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Length in words of the longest suffix of `previous` that is also a prefix of `next`
+fn boundary_overlap(previous: &str, next: &str) -> usize {
+    let previous_words: Vec<&str> = previous.split_whitespace().collect();
+    let next_words: Vec<&str> = next.split_whitespace().collect();
+    let max_overlap = previous_words.len().min(next_words.len());
+    (MIN_OVERLAP_WORDS..=max_overlap)
+        .rev()
+        .find(|&len| previous_words[previous_words.len() - len..] == next_words[..len])
+        .unwrap_or(0)
+}
+
+// The remainder of `text` after its first `count` words, keeping the original formatting
+fn skip_words(text: &str, count: usize) -> &str {
+    let mut rest = text;
+    for _ in 0..count {
+        rest = rest.trim_start();
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = &rest[word_end..];
+    }
+    rest
+}
+
+// Entries of a koboldai output; with `flatten_key`, every string under that key at any depth of an entry is
+// collected instead
+fn parse_koboldai_json(
+    contents: &str,
+    flatten_key: Option<&str>,
+) -> Result<Vec<(String, String)>, serde_json::Error> {
+    let json: Map<String, Value> = serde_json::from_str(contents)?;
     let mut texts = Vec::new();
-    for (_, value) in json.iter() {
-        if let Some(results) = value.get("results") {
+    for (filename, value) in json.iter().filter(|(filename, _)| !is_metadata_key(filename)) {
+        if let Some(key) = flatten_key {
+            let mut strings = Vec::new();
+            collect_strings_under(value, key, &mut strings);
+            texts.extend(strings.into_iter().map(|text| (filename.clone(), text.to_string())));
+        } else if let Some(text_str) = value.get("text").and_then(Value::as_str) {
+            // Entry stored as { "text": ..., "tokens": ... }
+            texts.push((filename.clone(), text_str.to_string()));
+        } else if let Some(results) = value.get("results") {
             if let Some(results_array) = results.as_array() {
                 for result in results_array {
                     if let Some(text) = result.get("text") {
                         if let Some(text_str) = text.as_str() {
-                            texts.push(("".to_string(), text_str.to_string()));
+                            texts.push((filename.clone(), text_str.to_string()));
                         }
                     }
                 }
@@ -112,17 +893,308 @@ fn parse_koboldai_json(contents: &str) -> Result<Vec<(String, String)>, serde_js
     Ok(texts)
 }
 
-fn parse_ollama_json(contents: &str) -> Result<Vec<(String, String)>, serde_json::Error> {
-    let json: BTreeMap<String, Value> = serde_json::from_str(&contents)?;
-    let texts = json
-        .iter()
-        .map(|(filename, text)| {
-            if let Some(text_str) = text.as_str() {
-                Ok((filename.clone(), text_str.to_string()))
-            } else {
-                Err(serde_json::Error::custom("Invalid JSON format"))
+// Collect, in document order, the strings held by `key` anywhere within a value; a value under `key` that is not
+// a string is searched further
+fn collect_strings_under<'a>(value: &'a Value, key: &str, strings: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(map) => {
+            for (name, child) in map {
+                match child.as_str() {
+                    Some(text) if name == key => strings.push(text),
+                    _ => collect_strings_under(child, key, strings),
+                }
             }
-        })
-        .collect::<Result<Vec<(String, String)>, serde_json::Error>>()?;
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_strings_under(item, key, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn parse_ollama_json(
+    contents: &str,
+    continue_on_parse_error: bool,
+) -> Result<Vec<(String, String)>, serde_json::Error> {
+    let json: Map<String, Value> = serde_json::from_str(contents)?;
+    let mut texts = Vec::new();
+    for (filename, text) in json.iter().filter(|(filename, _)| !is_metadata_key(filename)) {
+        // Entries written with --keep-done-reason hold the text under "response"
+        let text = text.get("response").unwrap_or(text);
+        if let Some(text_str) = text.as_str() {
+            texts.push((filename.clone(), text_str.to_string()));
+        } else if continue_on_parse_error {
+            // Skip the bad entry and keep merging the rest
+            eprintln!("Skipping {}: value is not a string", filename);
+        } else {
+            return Err(serde_json::Error::custom(format!(
+                "Invalid JSON format: value for {} is not a string",
+                filename
+            )));
+        }
+    }
     Ok(texts)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run the merger with the given arguments after the program name
+    fn run_merger(args: &[&str]) -> io::Result<()> {
+        run(std::iter::once("json_text_merger").chain(args.iter().copied()).map(String::from))
+    }
+
+    // Merge `json` in `mode` with the extra arguments, returning the merged output
+    fn merge(json: &str, mode: &str, extra: &[&str]) -> io::Result<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("summaries.json"), dir.path().join("merged.txt"));
+        fs::write(&input, json).unwrap();
+        let mut args = vec![input.to_str().unwrap(), output.to_str().unwrap(), mode];
+        args.extend(extra);
+        run_merger(&args)?;
+        fs::read_to_string(output)
+    }
+
+    const MIXED_OLLAMA_JSON: &str = r#"{
+        "part_1.txt": "First summary.",
+        "part_2.txt": {"error": "model not found"},
+        "part_3.txt": "Third summary."
+    }"#;
+
+    #[test]
+    fn parse_error_aborts_the_merge_by_default() {
+        let error = parse_ollama_json(MIXED_OLLAMA_JSON, false).unwrap_err();
+        assert!(error.to_string().contains("part_2.txt"));
+    }
+
+    #[test]
+    fn continue_on_parse_error_merges_the_valid_entries() {
+        let merged = merge(MIXED_OLLAMA_JSON, "ollama", &["--continue-on-parse-error"]).unwrap();
+        assert_eq!(merged, "First summary.\nThird summary.");
+    }
+
+    #[test]
+    fn dedupe_overlap_keeps_a_shared_boundary_sentence_once() {
+        let json = r#"{
+            "part_1.txt": "The team reviewed the budget. They agreed to cut travel costs.",
+            "part_2.txt": "They agreed to cut travel costs. Hiring resumes in March."
+        }"#;
+        let merged = merge(json, "ollama", &["--dedupe-overlap"]).unwrap();
+        assert_eq!(
+            merged,
+            "The team reviewed the budget. They agreed to cut travel costs.\nHiring resumes in March."
+        );
+        assert_eq!(merged.matches("They agreed to cut travel costs.").count(), 1);
+    }
+
+    #[test]
+    fn overlaps_shorter_than_the_minimum_are_kept() {
+        let mut texts = vec!["It was the end.".to_string(), "the end. Then more.".to_string()];
+        remove_boundary_overlaps(&mut texts);
+        assert_eq!(texts[1], "the end. Then more.");
+    }
+
+    #[test]
+    fn byte_order_mark_is_dropped_from_the_json_file() {
+        let json = "\u{feff}{\"part_1.txt\": {\"results\": [{\"text\": \"First summary.\"}]}}";
+        assert_eq!(merge(json, "koboldai", &[]).unwrap(), "First summary.");
+    }
+
+    #[test]
+    fn sort_strategies_order_the_entries() {
+        let json = r#"{
+            "part_10.txt": "Tenth summary here.",
+            "part_2.txt": "Second.",
+            "part_1.txt": "First one."
+        }"#;
+        let merged = |extra: &[&str]| merge(json, "ollama", extra).unwrap();
+        assert_eq!(merged(&[]), "First one.\nSecond.\nTenth summary here.");
+        assert_eq!(merged(&["--sort", "numeric"]), "First one.\nSecond.\nTenth summary here.");
+        assert_eq!(merged(&["--sort", "alpha"]), "First one.\nTenth summary here.\nSecond.");
+        assert_eq!(merged(&["--sort", "length"]), "Second.\nFirst one.\nTenth summary here.");
+        assert_eq!(merged(&["--sort", "none"]), "Tenth summary here.\nSecond.\nFirst one.");
+    }
+
+    #[test]
+    fn combine_json_rejects_conflicts_unless_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("batch_1.json"), r#"{"part_1.txt": "First.", "part_2.txt": "Second."}"#).unwrap();
+        fs::write(path("batch_2.json"), r#"{"part_2.txt": "Second, again.", "part_3.txt": "Third."}"#).unwrap();
+        let combine = |extra: &[&str]| {
+            let (output, first, second) = (path("combined.json"), path("batch_1.json"), path("batch_2.json"));
+            let mut args = vec!["--combine-json", &output, &first, &second];
+            args.extend(extra);
+            run_merger(&args).map(|()| serde_json::from_str::<Value>(&fs::read_to_string(&output).unwrap()).unwrap())
+        };
+
+        let error = combine(&[]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Conflicting entry for part_2.txt"));
+
+        let combined = combine(&["--overwrite-keys"]).unwrap();
+        assert_eq!(
+            combined,
+            serde_json::json!({"part_1.txt": "First.", "part_2.txt": "Second, again.", "part_3.txt": "Third."})
+        );
+    }
+
+    #[test]
+    fn front_matter_order_of_the_chunks_orders_the_entries() {
+        let chunks = tempfile::tempdir().unwrap();
+        fs::write(chunks.path().join("intro.txt"), "---\norder: 2\n---\nSecond chunk").unwrap();
+        fs::write(chunks.path().join("zeta.txt"), "---\norder: 1\n---\nFirst chunk").unwrap();
+        let json = r#"{"intro.txt": "Second summary.", "zeta.txt": "First summary."}"#;
+        let chunks_dir = chunks.path().to_str().unwrap();
+        let merged = merge(json, "ollama", &["--order-from", "frontmatter", "--chunks-dir", chunks_dir]).unwrap();
+        assert_eq!(merged, "First summary.\nSecond summary.");
+    }
+
+    #[test]
+    fn with_timing_marks_each_entry_with_its_chunk_time_range() {
+        let timing = tempfile::tempdir().unwrap();
+        fs::write(
+            timing.path().join("part_1.txt"),
+            "Script: Hello.\nStart Time: 1000\nEnd Time: 2500\n\nScript: Bye.\nStart Time: 3000\nEnd Time: 61000\n\n",
+        )
+        .unwrap();
+        // A byte order mark does not hide a time on the first line
+        fs::write(timing.path().join("part_2.txt"), "\u{feff}Start Time: 62000\nEnd Time: 3723000\nScript: Later.\n").unwrap();
+        let json = r#"{"part_1.txt": "First summary.", "part_2.txt": "Second summary."}"#;
+        let merged = merge(json, "ollama", &["--with-timing", timing.path().to_str().unwrap()]).unwrap();
+        assert_eq!(merged, "[00:00:01–00:01:01]\nFirst summary.\n[00:01:02–01:02:03]\nSecond summary.");
+    }
+
+    #[test]
+    fn csv_output_parses_back_to_the_entries() {
+        let json = r#"{
+            "part_2.txt": "Second, with a comma.",
+            "part_1.txt": "First line.\nSecond line with \"quotes\"."
+        }"#;
+        let merged = merge(json, "ollama", &["--format", "csv"]).unwrap();
+        let mut reader = csv::Reader::from_reader(merged.as_bytes());
+        assert_eq!(reader.headers().unwrap(), vec!["filename", "text"]);
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|record| record.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ["part_1.txt", "First line.\nSecond line with \"quotes\"."],
+                ["part_2.txt", "Second, with a comma."],
+            ]
+        );
+    }
+
+    #[test]
+    fn number_sections_counts_in_sorted_order() {
+        let json = r#"{"part_10.txt": "Third.", "part_2.txt": "Second.", "part_1.txt": "First."}"#;
+        let merged = merge(json, "ollama", &["--number-sections"]).unwrap();
+        assert_eq!(merged, "1. First.\n2. Second.\n3. Third.");
+    }
+
+    #[test]
+    fn with_metadata_heads_the_text_with_the_run_id() {
+        let json = r#"{"part_1.txt": "First.", "part_2.txt": "Second."}"#;
+        let merged = merge(json, "ollama", &["--with-metadata", "--run-id", "run-42"]).unwrap();
+        let lines: Vec<&str> = merged.lines().collect();
+        assert_eq!(lines[0], "_run_id: run-42");
+        assert!(lines[1].starts_with("_timestamp: "));
+        assert_eq!(lines[2..], ["", "First.", "Second."]);
+    }
+
+    #[test]
+    fn weights_repeat_or_mark_the_matching_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let weights = dir.path().join("weights.json");
+        fs::write(&weights, r#"{"*_2.txt": "IMPORTANT:", "*_3.txt": 2, "*_4.txt": 0}"#).unwrap();
+        let json = r#"{"part_1.txt": "First.", "part_2.txt": "Second.", "part_3.txt": "Third.", "part_4.txt": "Fourth."}"#;
+        let merged = merge(json, "ollama", &["--weights", weights.to_str().unwrap()]).unwrap();
+        assert_eq!(merged, "First.\nIMPORTANT: Second.\nThird.\nThird.");
+    }
+
+    #[test]
+    fn dedupe_sentences_keeps_the_first_occurrence() {
+        let json = r#"{
+            "part_1.txt": "The budget was approved. Travel is cut.",
+            "part_2.txt": "Hiring is frozen. The budget was  approved!",
+            "part_3.txt": "the budget was approved."
+        }"#;
+        let merged = merge(json, "ollama", &["--dedupe-sentences"]).unwrap();
+        assert_eq!(merged.matches("budget").count(), 1);
+        assert!(merged.starts_with("The budget was approved. Travel is cut."));
+        assert!(merged.contains("Hiring is frozen."));
+    }
+
+    #[test]
+    fn compare_reports_each_entry_and_diffs_the_changed_ones() {
+        let entries = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(name, text)| (name.to_string(), text.to_string())).collect()
+        };
+        let old = entries(&[("part_1.txt", "Same."), ("part_2.txt", "Old line.\nKept line."), ("part_3.txt", "Gone.")]);
+        let new = entries(&[("part_1.txt", "Same."), ("part_2.txt", "New line.\nKept line."), ("part_4.txt", "Added.")]);
+        assert_eq!(
+            compare_outputs(&old, &new, true),
+            "identical  part_1.txt\n\
+             changed    part_2.txt\n\
+             --- old/part_2.txt\n+++ new/part_2.txt\n@@ -1,2 +1,2 @@\n-Old line.\n+New line.\n Kept line.\n\
+             removed    part_3.txt\n\
+             added      part_4.txt\n\
+             1 identical, 1 changed, 1 added, 1 removed\n"
+        );
+    }
+
+    #[test]
+    fn flatten_collects_nested_text_fields_in_order() {
+        let json = r#"{
+            "part_1.txt": {"results": [{"text": "First."}, {"extra": {"text": "Nested."}}]},
+            "part_2.txt": {"outer": [{"inner": {"text": "Deep."}}, {"text": "Last."}]}
+        }"#;
+        let merged = merge(json, "koboldai", &["--flatten"]).unwrap();
+        assert_eq!(merged, "First.\nNested.\nDeep.\nLast.");
+        let json = r#"{"part_1.txt": {"choices": [{"content": "Custom key."}]}}"#;
+        let merged = merge(json, "koboldai", &["--flatten", "--flatten-key", "content"]).unwrap();
+        assert_eq!(merged, "Custom key.");
+    }
+
+    #[test]
+    fn entries_are_merged_in_natural_filename_order() {
+        let json = r#"{
+            "ep1_part10.txt": "Tenth.",
+            "ep1_part2.txt": "Second.",
+            "ep1_part1.txt": "First."
+        }"#;
+        assert_eq!(merge(json, "ollama", &[]).unwrap(), "First.\nSecond.\nTenth.");
+    }
+
+    #[test]
+    fn source_dir_interleaves_each_original_with_its_summary() {
+        let sources = tempfile::tempdir().unwrap();
+        fs::write(sources.path().join("part_1.txt"), "First original.\n").unwrap();
+        fs::write(sources.path().join("part_2.txt"), "Second original.\n").unwrap();
+        let json = r#"{ "part_2.txt": "Second summary.", "part_1.txt": "First summary." }"#;
+        let merged = merge(json, "ollama", &["--source-dir", sources.path().to_str().unwrap()]).unwrap();
+        assert_eq!(
+            merged,
+            "ORIGINAL:\nFirst original.\nSUMMARY:\nFirst summary.\n\n\
+             ORIGINAL:\nSecond original.\nSUMMARY:\nSecond summary.\n"
+        );
+    }
+
+    #[test]
+    fn outline_nests_bullet_summaries_under_their_chunks() {
+        let json = r#"{
+            "part_1.txt": "- Budget approved\n  * Travel cut by 10%\n- Hiring paused",
+            "part_2.txt": "1. Roadmap reviewed\n2) Launch moved to May"
+        }"#;
+        let merged = merge(json, "ollama", &["--format", "outline"]).unwrap();
+        assert_eq!(
+            merged,
+            "- part_1\n  - Budget approved\n    - Travel cut by 10%\n  - Hiring paused\n\
+             - part_2\n  - Roadmap reviewed\n  - Launch moved to May\n"
+        );
+    }
+}
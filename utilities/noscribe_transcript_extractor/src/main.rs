@@ -46,10 +46,10 @@ struct ScriptAndDuration {
 // Function to extract script text and its corresponding start and end times from an HTML file
 fn extract_script_and_duration_from_html(html_file: &str) -> Vec<ScriptAndDuration> {
     let mut script_and_duration = Vec::new();
-    // Open the HTML file
-    if let Ok(file) = File::open(html_file) {
-        // Parse the HTML document
-        let document = Document::from_read(file).expect("Failed to parse HTML");
+    // Read the HTML file
+    if let Ok(html) = std::fs::read_to_string(html_file) {
+        // Parse the HTML document, dropping a leading UTF-8 byte order mark
+        let document = Document::from(html.strip_prefix('\u{feff}').unwrap_or(&html));
         // Find all <a> elements with a 'name' attribute starting with 'ts_'
         for node in document.find(Name("a").and(|n: &select::node::Node| n.attr("name").is_some())) {
             if let Some(name) = node.attr("name") {
@@ -61,9 +61,9 @@ fn extract_script_and_duration_from_html(html_file: &str) -> Vec<ScriptAndDurati
                         if let (Ok(start_time), Ok(end_time)) = (parts[1].parse::<i32>(), parts[2].parse::<i32>()) {
                             // Create a ScriptAndDuration instance and add it to the vector
                             script_and_duration.push(ScriptAndDuration {
-                                script: script,
-                                start_time: start_time,
-                                end_time: end_time,
+                                script,
+                                start_time,
+                                end_time,
                             });
                         }
                     }
@@ -92,14 +92,12 @@ fn convert_html_file(html_file: &Path, output_dir: &Path) {
 
 // Function to convert all HTML files in a directory to transcript text files
 fn convert_html_files_in_directory(directory: &Path, output_dir: &Path) {
-    for entry in std::fs::read_dir(directory).expect("Failed to read directory") {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            if let Some(extension) = path.extension() {
-                if extension == "html" {
-                    // Convert HTML file to transcript text file
-                    convert_html_file(&path, output_dir);
-                }
+    for entry in std::fs::read_dir(directory).expect("Failed to read directory").flatten() {
+        let path = entry.path();
+        if let Some(extension) = path.extension() {
+            if extension == "html" {
+                // Convert HTML file to transcript text file
+                convert_html_file(&path, output_dir);
             }
         }
     }
@@ -132,3 +130,27 @@ fn main() {
     println!("Conversion completed.");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_order_mark_is_dropped_before_parsing() {
+        let dir = tempfile::tempdir().unwrap();
+        let html_file = dir.path().join("interview.html");
+        std::fs::write(
+            &html_file,
+            "\u{feff}<html><body><p><a name=\"ts_0_1500_S1\">Skipped</a><a name=\"ts_0_1500\">Hello there.</a></p></body></html>",
+        )
+        .unwrap();
+        let extracted = extract_script_and_duration_from_html(html_file.to_str().unwrap());
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0].script, "Hello there.");
+        assert_eq!((extracted[0].start_time, extracted[0].end_time), (0, 1500));
+
+        convert_html_file(&html_file, dir.path());
+        let transcript = std::fs::read_to_string(dir.path().join("interview.txt")).unwrap();
+        assert_eq!(transcript, "Script: Hello there.\nStart Time: 0\nEnd Time: 1500\n\n");
+    }
+}
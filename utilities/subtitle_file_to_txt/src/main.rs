@@ -4,65 +4,201 @@
 //
 // Description:
 // subtitle_file_to_txt is a Rust program designed to convert subtitles from .srt files into a custom text format.
-// It takes the path of an .srt file as a command-line argument, parses the subtitle file, and writes the
-// converted subtitles into a text file with timestamps.
+// It takes the path of an .srt file (or a directory of .srt files) as a command-line argument, parses the subtitle
+// file, and writes the converted subtitles into a text file with timestamps.
 //
 // How to Use:
 // 1. Compile the code using the Rust compiler.
-// 2. Run the executable with a command-line argument representing the path to the .srt file.
+// 2. Run the executable with a command-line argument representing the path to the .srt file, or a directory
+//    containing .srt files. An optional second argument sets the output directory. A single file is converted to
+//    <name>.txt in the current directory when no output directory is given. In directory mode each .srt file is
+//    converted to <name>.txt, written next to its source when no output directory is given.
+//    Files in a directory are converted concurrently; --jobs <n> limits the number of worker threads
+//    (defaults to the number of available CPUs).
+// 3. Add --split -s <max_tokens_per_split> [-c <config_file>] to split the converted transcript straight into chunk
+//    files using the transcript splitter's logic, skipping the intermediate text file. Parts are written to the
+//    output directory, or to <name>_splits (in the current directory for a single file, next to the source file
+//    in directory mode) when none is given.
+// 4. Add --script-label <label>, --start-label <label> and --end-label <label> to rename the "Script", "Start Time"
+//    and "End Time" labels written in the transcript. The chunk files written by --split never contain the labels.
+// 5. Add --strip-nonverbal to remove bracketed or parenthesized annotations such as [music] or (laughs) from the
+//    cue text.
+// 6. Add --fix-timing to repair auto-generated timestamps: a cue ending before it starts gets its end time set to its
+//    start time, and cues starting before the previous cue or before it ends are reported on stderr. Add
+//    --sort-cues to re-sort the cues by start time.
+// 7. Add --format json to write a JSON array of {"index", "start_ms", "end_ms", "text"} objects, one per cue,
+//    instead of the text format, written to <name>.json.
+// 8. MicroDVD .sub files ({start_frame}{end_frame}text lines, with | separating the lines of a cue) are converted
+//    too, alone or alongside .srt files in a directory. Add --fps <rate> to convert their frame numbers to
+//    milliseconds; without it the frame rate of a leading {1}{1}<rate> line is used.
 //
 // Example Usage:
 // $ cargo run -- <path_to_srt_file>
+// $ cargo run -- <path_to_srt_directory> [output_directory] [--jobs <n>]
+// $ cargo run -- <path_to_srt_file> [output_directory] --split -s 1000 -c config.json
+// $ cargo run -- <path_to_srt_file> --script-label Text --start-label Start --end-label End
+// $ cargo run -- <path_to_srt_file> --format json
+// $ cargo run -- <path_to_sub_file> --fps 25
 //
 // This program provides a convenient way to convert .srt subtitles into a more readable format for further analysis or use.
 
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::{self, prelude::*, BufReader};
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use indicatif::{ProgressBar, ProgressStyle};
 use regex::Regex;
+use serde_json::json;
+use transcript_splitter::{read_config, split_content, Config, LineEnding, PartFormat, SplitOptions, DEFAULT_NAME_TEMPLATE};
 
-fn main() -> io::Result<()> {
-    // Get command-line arguments
-    let args: Vec<String> = env::args().collect();
+// Settings for splitting converted subtitles straight into chunk files (--split)
+struct SplitSettings {
+    max_tokens_per_split: usize,
+    config: Config,
+}
 
-    // Check if the user provided the path to the .srt file as an argument
-    if args.len() != 2 {
-        println!("Usage: cargo run -- <path_to_srt_file>");
-        return Ok(());
+// Output format of the converted subtitles
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    // Labeled text blocks read by the transcript splitter
+    Text,
+    // A JSON array with one object per cue
+    Json,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Json => "json",
+        }
     }
+}
 
-    let srt_file_path = &args[1];
+// Options applied while parsing the cues of a subtitle file
+#[derive(Clone, Copy, Default)]
+struct CueOptions {
+    strip_nonverbal: bool,
+    fix_timing: bool,
+    sort_cues: bool,
+    // Frame rate of MicroDVD .sub files, overriding the one given in the file
+    fps: Option<f64>,
+}
 
-    // Open the .srt file
-    let file = File::open(srt_file_path)?;
-    let reader = BufReader::new(file);
+// A single subtitle cue
+struct Cue {
+    index: u64,
+    start_ms: u64,
+    end_ms: u64,
+    text: String,
+}
 
+// Labels written before each cue's script text and times
+struct Labels {
+    script: String,
+    start: String,
+    end: String,
+}
+
+// The labels the transcript splitter recognizes
+impl Default for Labels {
+    fn default() -> Self {
+        Labels {
+            script: "Script".to_string(),
+            start: "Start Time".to_string(),
+            end: "End Time".to_string(),
+        }
+    }
+}
+
+impl Labels {
+    fn format_cue(&self, script: &str, start_ms: u64, end_ms: u64) -> String {
+        format!(
+            "{}: {}\n{}: {:?}\n{}: {:?}\n\n",
+            self.script, script, self.start, start_ms, self.end, end_ms
+        )
+    }
+}
+
+// Whether a subtitle file is in the SubRip format, whatever the case of its extension
+fn is_srt(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("srt"))
+}
+
+// Whether a subtitle file is in the MicroDVD format rather than SubRip
+fn is_microdvd(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("sub"))
+}
+
+// Parse the cues of a single .srt or MicroDVD .sub file
+fn parse_subtitles(subtitle_file_path: &Path, options: CueOptions) -> io::Result<Vec<Cue>> {
+    // Read the file, dropping a leading UTF-8 byte order mark that would hide the first cue index
+    let content = fs::read_to_string(subtitle_file_path)?;
+    let content = content.strip_prefix('\u{feff}').unwrap_or(&content);
+
+    // Non-verbal annotations such as [music], [applause] or (laughs)
+    let nonverbal_regex = options.strip_nonverbal.then(|| Regex::new(r"\[[^\]]*\]|\([^)]*\)").unwrap());
+
+    let mut cues = if is_microdvd(subtitle_file_path) {
+        parse_microdvd(content, options.fps, nonverbal_regex.as_ref())?
+    } else {
+        parse_srt(content, nonverbal_regex.as_ref())
+    };
+
+    if options.fix_timing {
+        for warning in fix_cue_timing(&mut cues, subtitle_file_path, options.sort_cues) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    if options.sort_cues {
+        // A stable sort keeps cues with the same start time in file order
+        cues.sort_by_key(|cue| cue.start_ms);
+    }
+
+    Ok(cues)
+}
+
+// A line of cue text, trimmed and with non-verbal annotations removed when `nonverbal_regex` is given, or None
+// when nothing is left
+fn cue_line(line: &str, nonverbal_regex: Option<&Regex>) -> Option<String> {
+    let line = match nonverbal_regex {
+        Some(nonverbal_regex) => nonverbal_regex.replace_all(line, "").split_whitespace().collect::<Vec<_>>().join(" "),
+        None => line.trim().to_string(),
+    };
+    (!line.is_empty()).then_some(line)
+}
+
+// Parse the cues of .srt content
+fn parse_srt(content: &str, nonverbal_regex: Option<&Regex>) -> Vec<Cue> {
     // Regular expression to match the subtitle time format
     let time_regex = Regex::new(r"(\d{2}):(\d{2}):(\d{2}),(\d{3}) --> (\d{2}):(\d{2}):(\d{2}),(\d{3})").unwrap();
 
     // Variables to store parsed subtitle data
-    let mut subtitles = String::new();
-    let mut current_script = String::new();
+    let mut cues = Vec::new();
+    // Lines of the current cue, joined once when the cue ends rather than appended to a growing string
+    let mut current_script: Vec<String> = Vec::new();
+    let mut index_line = None;
+    let mut index = 0;
     let mut start_ms = 0;
     let mut end_ms = 0;
 
     // Parse the .srt file
-    for line in reader.lines() {
-        let line = line?;
-
+    for line in content.lines() {
         // Check if the line matches the time format
-        if let Some(captures) = time_regex.captures(&line) {
+        if let Some(captures) = time_regex.captures(line) {
             if !current_script.is_empty() {
                 // Add the current script to subtitles before starting a new one
-                subtitles += &format!(
-                    "Script: {}\nStart Time: {:?}\nEnd Time: {:?}\n\n",
-                    current_script.trim(),
-                    start_ms,
-                    end_ms
-                );
+                cues.push(Cue { index, start_ms, end_ms, text: current_script.join(" ") });
                 current_script.clear();
             }
 
+            // Use the cue's index line, or count cues when it is missing
+            index = index_line.take().unwrap_or(cues.len() as u64 + 1);
+
             // Parse start and end times in milliseconds
             start_ms = (captures[1].parse::<u64>().unwrap() * 3600 * 1000)
                 + (captures[2].parse::<u64>().unwrap() * 60 * 1000)
@@ -73,26 +209,627 @@ fn main() -> io::Result<()> {
                 + (captures[6].parse::<u64>().unwrap() * 60 * 1000)
                 + (captures[7].parse::<u64>().unwrap() * 1000)
                 + captures[8].parse::<u64>().unwrap();
+        } else if !line.trim().is_empty() && line.trim().chars().all(|c| c.is_ascii_digit()) {
+            index_line = line.trim().parse().ok();
         } else if !line.trim().is_empty() && !line.chars().all(char::is_numeric) {
             // Collect subtitle script text (ignoring the index line and blank lines)
-            current_script += &format!(" {}", line.trim());
+            current_script.extend(cue_line(line, nonverbal_regex));
         }
     }
 
     // Add the last script if any
     if !current_script.is_empty() {
-        subtitles += &format!(
-            "Script: {}\nStart Time: {:?}\nEnd Time: {:?}\n\n",
-            current_script.trim(),
-            start_ms,
-            end_ms
-        );
+        cues.push(Cue { index, start_ms, end_ms, text: current_script.join(" ") });
+    }
+
+    cues
+}
+
+// Parse the cues of MicroDVD content: "{start_frame}{end_frame}text" lines, where "|" separates the lines of a
+// cue and further {...} codes set formatting. Frames are converted with `fps`, or with the frame rate of a
+// leading "{1}{1}<fps>" line.
+fn parse_microdvd(content: &str, fps: Option<f64>, nonverbal_regex: Option<&Regex>) -> io::Result<Vec<Cue>> {
+    let cue_regex = Regex::new(r"^\{(\d+)\}\{(\d*)\}(.*)$").unwrap();
+    let format_code_regex = Regex::new(r"\{[^}]*\}").unwrap();
+
+    let mut frames = Vec::new();
+    let mut file_fps = None;
+    for line in content.lines() {
+        let Some(captures) = cue_regex.captures(line.trim()) else {
+            continue;
+        };
+        let start_frame: u64 = captures[1].parse().unwrap_or(0);
+        let end_frame: u64 = captures[2].parse().unwrap_or(start_frame);
+        // A leading {1}{1} cue holding only a number gives the frame rate rather than text
+        if frames.is_empty() && file_fps.is_none() && start_frame == 1 && end_frame == 1 {
+            if let Ok(rate) = captures[3].trim().parse::<f64>() {
+                file_fps = Some(rate);
+                continue;
+            }
+        }
+        let text = format_code_regex.replace_all(&captures[3], "");
+        let lines: Vec<String> = text.split('|').filter_map(|line| cue_line(line, nonverbal_regex)).collect();
+        if !lines.is_empty() {
+            frames.push((start_frame, end_frame, lines.join(" ")));
+        }
+    }
+
+    let fps = fps.or(file_fps).filter(|&fps| fps > 0.0).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "MicroDVD subtitles need a frame rate (--fps <rate>)")
+    })?;
+    let frame_ms = |frame: u64| (frame as f64 * 1000.0 / fps).round() as u64;
+    Ok(frames
+        .into_iter()
+        .enumerate()
+        .map(|(position, (start_frame, end_frame, text))| Cue {
+            index: position as u64 + 1,
+            start_ms: frame_ms(start_frame),
+            end_ms: frame_ms(end_frame),
+            text,
+        })
+        .collect())
+}
+
+// Clamp each cue's end time to at least its start time, returning a warning for each such cue, each cue that
+// starts before the previous one and each cue that overlaps the previous one
+fn fix_cue_timing(cues: &mut [Cue], subtitle_file_path: &Path, sorted: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut previous_start_ms = 0;
+    let mut previous_end_ms = 0;
+    for cue in cues.iter_mut() {
+        if cue.end_ms < cue.start_ms {
+            warnings.push(format!(
+                "{}: cue {} ends before it starts; its end time is set to its start time",
+                subtitle_file_path.display(),
+                cue.index
+            ));
+            cue.end_ms = cue.start_ms;
+        }
+        if cue.start_ms < previous_start_ms {
+            warnings.push(format!(
+                "{}: cue {} starts before the previous cue{}",
+                subtitle_file_path.display(),
+                cue.index,
+                if sorted { "; the cues are re-sorted" } else { " (use --sort-cues to reorder)" }
+            ));
+        } else if cue.start_ms < previous_end_ms {
+            warnings.push(format!(
+                "{}: cue {} starts before the previous cue ends",
+                subtitle_file_path.display(),
+                cue.index
+            ));
+        }
+        previous_start_ms = cue.start_ms;
+        previous_end_ms = cue.end_ms;
+    }
+    warnings
+}
+
+// Convert a single subtitle file into the custom transcript text format, or a JSON array of cues
+fn convert_srt(subtitle_file_path: &Path, labels: &Labels, cue_options: CueOptions, format: Format) -> io::Result<String> {
+    let cues = parse_subtitles(subtitle_file_path, cue_options)?;
+    match format {
+        Format::Text => Ok(cues
+            .iter()
+            .map(|cue| labels.format_cue(&cue.text, cue.start_ms, cue.end_ms))
+            .collect()),
+        Format::Json => {
+            let records: Vec<_> = cues
+                .iter()
+                .map(|cue| json!({"index": cue.index, "start_ms": cue.start_ms, "end_ms": cue.end_ms, "text": cue.text}))
+                .collect();
+            Ok(serde_json::to_string_pretty(&records)? + "\n")
+        }
+    }
+}
+
+// Split converted subtitles into chunk files named after `stem`, returning the number of parts
+fn split_subtitles(subtitles: &str, stem: &str, output_dir: &Path, split: &SplitSettings) -> io::Result<usize> {
+    let options = SplitOptions {
+        max_tokens_per_split: split.max_tokens_per_split,
+        continuation_marker: false,
+        name_template: DEFAULT_NAME_TEMPLATE,
+        line_ending: LineEnding::default(),
+        delimiter: None,
+        respect_markdown: false,
+        format: PartFormat::Text,
+    };
+    let result = split_content(subtitles, stem, ".txt", &split.config, &output_dir.to_string_lossy(), &options)?;
+    Ok(result.part_sizes.len())
+}
+
+// Convert a single subtitle file from a directory, writing <name>.txt (or <name>.json) into the output directory
+// (or next to the source file when no output directory is given)
+fn convert_srt_file(
+    path: &Path,
+    output_dir: Option<&Path>,
+    split: Option<&SplitSettings>,
+    labels: &Labels,
+    cue_options: CueOptions,
+    format: Format,
+) -> io::Result<()> {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+
+    // With --split, write chunk files instead of the converted transcript; the splitter strips the default labels
+    if let Some(split) = split {
+        let subtitles = convert_srt(path, &Labels::default(), cue_options, Format::Text)?;
+        let split_dir = match output_dir {
+            Some(output_dir) => output_dir.to_path_buf(),
+            None => path.with_file_name(format!("{}_splits", stem)),
+        };
+        split_subtitles(&subtitles, &stem, &split_dir, split)?;
+        return Ok(());
+    }
+
+    let subtitles = convert_srt(path, labels, cue_options, format)?;
+
+    let txt_file_name = format!("{}.{}", stem, format.extension());
+    let txt_file_path = match output_dir {
+        Some(output_dir) => output_dir.join(txt_file_name),
+        None => path.with_file_name(txt_file_name),
+    };
+    let mut output_file = File::create(txt_file_path)?;
+    output_file.write_all(subtitles.as_bytes())
+}
+
+// Convert every .srt and .sub file in a directory using up to `jobs` worker threads
+fn convert_srt_directory(
+    directory: &Path,
+    output_dir: Option<&Path>,
+    jobs: usize,
+    split: Option<&SplitSettings>,
+    labels: &Labels,
+    cue_options: CueOptions,
+    format: Format,
+) -> io::Result<usize> {
+    if let Some(output_dir) = output_dir {
+        fs::create_dir_all(output_dir)?;
+    }
+
+    let mut srt_files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if is_srt(&path) || is_microdvd(&path) {
+            srt_files.push(path);
+        }
+    }
+
+    // An .srt and a .sub file of the same name would be converted to the same output file, so neither is converted
+    srt_files.sort();
+    let mut stems = HashMap::new();
+    for path in &srt_files {
+        if let Some(other) = stems.insert(path.file_stem().unwrap().to_os_string(), path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} would be written to the same output; rename one of them",
+                    other.display(),
+                    path.display()
+                ),
+            ));
+        }
+    }
+
+    let pb = ProgressBar::new(srt_files.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({percent}%)")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+
+    // Each worker takes the next unconverted file until none are left
+    let next_file = AtomicUsize::new(0);
+    let errors = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, srt_files.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next_file.fetch_add(1, Ordering::SeqCst);
+                let Some(path) = srt_files.get(index) else {
+                    break;
+                };
+                if let Err(e) = convert_srt_file(path, output_dir, split, labels, cue_options, format) {
+                    errors.lock().unwrap().push(format!("{}: {}", path.display(), e));
+                }
+                pb.inc(1);
+            });
+        }
+    });
+    pb.finish();
+
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        return Err(io::Error::other(errors.join("\n")));
     }
+    Ok(srt_files.len())
+}
 
-    // Write the parsed subtitles to a text file
-    let mut output_file = File::create("converted_subtitles.txt")?;
+fn main() -> io::Result<()> {
+    run(env::args())
+}
+
+// Convert the subtitles as the command-line arguments say, the program name first
+fn run(args: impl Iterator<Item = String>) -> io::Result<()> {
+    let mut args = args.skip(1);
+    let mut positional = Vec::new();
+    let mut jobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut split = false;
+    let mut max_tokens_per_split = None;
+    let mut config_file = None;
+    let mut labels = Labels::default();
+    let mut cue_options = CueOptions::default();
+    let mut format = Format::Text;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--jobs" => {
+                if let Some(count) = args.next().and_then(|n| n.parse::<usize>().ok()) {
+                    jobs = count;
+                } else {
+                    println!("Error: Invalid value for --jobs");
+                    return Ok(());
+                }
+            }
+            "--split" => {
+                split = true;
+            }
+            "-s" => {
+                if let Some(tokens) = args.next().and_then(|t| t.parse::<usize>().ok()).filter(|&t| t > 0) {
+                    max_tokens_per_split = Some(tokens);
+                } else {
+                    println!("Error: Invalid value for max tokens per split");
+                    return Ok(());
+                }
+            }
+            "-c" => {
+                if let Some(file) = args.next() {
+                    config_file = Some(file);
+                } else {
+                    println!("Error: Expected config file after -c flag");
+                    return Ok(());
+                }
+            }
+            "--format" => match args.next().as_deref() {
+                Some("text") => format = Format::Text,
+                Some("json") => format = Format::Json,
+                _ => {
+                    println!("Error: Invalid value for --format (expected text or json)");
+                    return Ok(());
+                }
+            },
+            "--fps" => {
+                if let Some(fps) = args.next().and_then(|rate| rate.parse::<f64>().ok()).filter(|&rate| rate > 0.0) {
+                    cue_options.fps = Some(fps);
+                } else {
+                    println!("Error: Invalid value for --fps");
+                    return Ok(());
+                }
+            }
+            "--strip-nonverbal" => {
+                cue_options.strip_nonverbal = true;
+            }
+            "--fix-timing" => {
+                cue_options.fix_timing = true;
+            }
+            "--sort-cues" => {
+                cue_options.sort_cues = true;
+            }
+            "--script-label" | "--start-label" | "--end-label" => {
+                let Some(label) = args.next() else {
+                    println!("Error: Expected label after {} flag", arg);
+                    return Ok(());
+                };
+                match arg.as_str() {
+                    "--script-label" => labels.script = label,
+                    "--start-label" => labels.start = label,
+                    _ => labels.end = label,
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    // Check if the user provided the path to the .srt file or directory as an argument
+    if positional.len() != 1 && positional.len() != 2 {
+        println!("Usage: cargo run -- <path_to_srt_file|path_to_srt_directory> [output_directory] [--jobs <n>] [--split -s <max_tokens_per_split> [-c <config_file>]] [--script-label <label>] [--start-label <label>] [--end-label <label>] [--strip-nonverbal] [--fix-timing] [--sort-cues] [--fps <rate>] [--format text|json]");
+        return Ok(());
+    }
+
+    if split && format == Format::Json {
+        println!("Error: --format json cannot be combined with --split");
+        return Ok(());
+    }
+
+    // Read the splitter configuration once for every file
+    let split = if split {
+        let Some(max_tokens_per_split) = max_tokens_per_split else {
+            println!("Error: --split requires a max tokens per split argument (-s)");
+            return Ok(());
+        };
+        Some(SplitSettings {
+            max_tokens_per_split,
+            config: read_config(config_file.as_deref(), None)?,
+        })
+    } else {
+        None
+    };
+
+    let input_path = Path::new(&positional[0]);
+    let output_dir = positional.get(1).map(Path::new);
+
+    if input_path.is_dir() {
+        // Convert every .srt and .sub file in the directory
+        let converted = convert_srt_directory(input_path, output_dir, jobs, split.as_ref(), &labels, cue_options, format)?;
+        println!("{} subtitle files converted successfully.", converted);
+        return Ok(());
+    }
+
+    if let Some(split) = &split {
+        // Write chunk files straight from the converted subtitles
+        let subtitles = convert_srt(input_path, &Labels::default(), cue_options, Format::Text)?;
+        let stem = input_path.file_stem().unwrap().to_string_lossy();
+        let split_dir = match output_dir {
+            Some(output_dir) => output_dir.to_path_buf(),
+            None => env::current_dir()?.join(format!("{}_splits", stem)),
+        };
+        let parts = split_subtitles(&subtitles, &stem, &split_dir, split)?;
+        println!("Subtitles converted and split into {} parts.", parts);
+        return Ok(());
+    }
+
+    // Write the parsed subtitles to a text (or JSON) file
+    let subtitles = convert_srt(input_path, &labels, cue_options, format)?;
+    // Name the output after the input so converting several files into one directory keeps every result
+    let stem = input_path.file_stem().unwrap().to_string_lossy();
+    let output_file_name = format!("{}.{}", stem, format.extension());
+    let output_file_path = match output_dir {
+        Some(output_dir) => {
+            fs::create_dir_all(output_dir)?;
+            output_dir.join(output_file_name)
+        }
+        None => Path::new(&output_file_name).to_path_buf(),
+    };
+    let mut output_file = File::create(&output_file_path)?;
     output_file.write_all(subtitles.as_bytes())?;
 
-    println!("Subtitles converted successfully.");
+    println!("Subtitles converted successfully to {}.", output_file_path.display());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "1\n00:00:01,000 --> 00:00:02,500\nHello there.\n\n2\n00:00:03,000 --> 00:00:04,000\nGeneral Kenobi.\n";
+
+    // A directory holding the given files
+    fn subtitle_dir(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn directory_conversion_writes_a_text_file_per_srt_file() {
+        let dir = subtitle_dir(&[("episode1.srt", SRT), ("EPISODE2.SRT", SRT), ("notes.md", "Not subtitles")]);
+        let output = tempfile::tempdir().unwrap();
+        let converted = convert_srt_directory(
+            dir.path(),
+            Some(output.path()),
+            2,
+            None,
+            &Labels::default(),
+            CueOptions::default(),
+            Format::Text,
+        )
+        .unwrap();
+        assert_eq!(converted, 2);
+        let mut written: Vec<String> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        written.sort();
+        assert_eq!(written, ["EPISODE2.txt", "episode1.txt"]);
+        let text = fs::read_to_string(output.path().join("episode1.txt")).unwrap();
+        assert_eq!(
+            text,
+            "Script: Hello there.\nStart Time: 1000\nEnd Time: 2500\n\nScript: General Kenobi.\nStart Time: 3000\nEnd Time: 4000\n\n"
+        );
+    }
+
+    #[test]
+    fn concurrent_directory_conversion_converts_every_file() {
+        let files: Vec<(String, String)> = (1..=8)
+            .map(|n| {
+                let srt = format!("1\n00:00:0{},000 --> 00:00:0{},500\nLine of episode {}.\n", n, n, n);
+                (format!("episode{}.srt", n), srt)
+            })
+            .collect();
+        let entries: Vec<(&str, &str)> = files.iter().map(|(name, srt)| (name.as_str(), srt.as_str())).collect();
+        let dir = subtitle_dir(&entries);
+        let converted = convert_srt_directory(
+            dir.path(),
+            None,
+            4,
+            None,
+            &Labels::default(),
+            CueOptions::default(),
+            Format::Text,
+        )
+        .unwrap();
+        assert_eq!(converted, 8);
+        for n in 1..=8 {
+            let text = fs::read_to_string(dir.path().join(format!("episode{}.txt", n))).unwrap();
+            assert_eq!(
+                text,
+                format!("Script: Line of episode {}.\nStart Time: {}000\nEnd Time: {}500\n\n", n, n, n)
+            );
+        }
+    }
+
+    #[test]
+    fn directory_conversion_reports_the_files_that_failed() {
+        let dir = subtitle_dir(&[("good.srt", SRT), ("frames.sub", "{10}{20}No frame rate")]);
+        let error = convert_srt_directory(
+            dir.path(),
+            None,
+            2,
+            None,
+            &Labels::default(),
+            CueOptions::default(),
+            Format::Text,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("frames.sub"));
+        assert!(dir.path().join("good.txt").exists());
+    }
+
+    #[test]
+    fn srt_and_sub_files_of_the_same_name_are_not_converted() {
+        let dir = subtitle_dir(&[("episode.srt", SRT), ("episode.sub", "{25}{50}Hello.\n")]);
+        let error = convert_srt_directory(
+            dir.path(),
+            None,
+            2,
+            None,
+            &Labels::default(),
+            CueOptions::default(),
+            Format::Text,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("episode.srt and "), "{}", error);
+        assert!(error.to_string().contains("episode.sub would be written to the same output"), "{}", error);
+        assert!(!dir.path().join("episode.txt").exists());
+    }
+
+    #[test]
+    fn byte_order_mark_does_not_hide_the_first_cue() {
+        let dir = subtitle_dir(&[("episode.srt", &format!("\u{feff}{}", SRT))]);
+        let cues = parse_subtitles(&dir.path().join("episode.srt"), CueOptions::default()).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!((cues[0].index, cues[0].start_ms, cues[0].text.as_str()), (1, 1000, "Hello there."));
+    }
+
+    #[test]
+    fn split_writes_chunk_files_straight_from_the_subtitles() {
+        let dir = subtitle_dir(&[("episode.srt", SRT)]);
+        let output = tempfile::tempdir().unwrap();
+        let split = SplitSettings {
+            max_tokens_per_split: 3,
+            config: read_config(None, Some(r#"{"header":"Summarize:\n"}"#)).unwrap(),
+        };
+        convert_srt_file(
+            &dir.path().join("episode.srt"),
+            Some(output.path()),
+            Some(&split),
+            &Labels::default(),
+            CueOptions::default(),
+            Format::Text,
+        )
+        .unwrap();
+        let mut parts: Vec<String> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        parts.sort();
+        assert_eq!(parts, ["episode_part_001.txt", "episode_part_002.txt"]);
+        let first = fs::read_to_string(output.path().join("episode_part_001.txt")).unwrap();
+        assert_eq!(first, "Summarize:\nHello there. General\n\n");
+        // No intermediate transcript is written
+        assert!(!dir.path().join("episode.txt").exists());
+    }
+
+    #[test]
+    fn custom_labels_replace_the_default_ones() {
+        let dir = subtitle_dir(&[("episode.srt", SRT)]);
+        let labels = Labels { script: "Text".to_string(), start: "Start".to_string(), end: "End".to_string() };
+        let text = convert_srt(&dir.path().join("episode.srt"), &labels, CueOptions::default(), Format::Text).unwrap();
+        assert_eq!(
+            text,
+            "Text: Hello there.\nStart: 1000\nEnd: 2500\n\nText: General Kenobi.\nStart: 3000\nEnd: 4000\n\n"
+        );
+    }
+
+    #[test]
+    fn strip_nonverbal_removes_annotations_but_keeps_dialogue() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\n[music]\n\n2\n00:00:03,000 --> 00:00:04,000\n(laughs) That was great,\n[applause] really.\n";
+        let dir = subtitle_dir(&[("episode.srt", srt)]);
+        let path = dir.path().join("episode.srt");
+        let texts = |cue_options: CueOptions| -> Vec<String> {
+            parse_subtitles(&path, cue_options).unwrap().into_iter().map(|cue| cue.text).collect()
+        };
+        let stripped = texts(CueOptions { strip_nonverbal: true, ..CueOptions::default() });
+        assert_eq!(stripped, ["That was great, really."]);
+        let kept = texts(CueOptions::default());
+        assert_eq!(kept, ["[music]", "(laughs) That was great, [applause] really."]);
+    }
+
+    #[test]
+    fn json_format_lists_the_cues_in_milliseconds() {
+        let dir = subtitle_dir(&[("episode.srt", SRT)]);
+        let converted = convert_srt(&dir.path().join("episode.srt"), &Labels::default(), CueOptions::default(), Format::Json)
+            .unwrap();
+        let records: serde_json::Value = serde_json::from_str(&converted).unwrap();
+        assert_eq!(
+            records,
+            json!([
+                {"index": 1, "start_ms": 1000, "end_ms": 2500, "text": "Hello there."},
+                {"index": 2, "start_ms": 3000, "end_ms": 4000, "text": "General Kenobi."},
+            ])
+        );
+    }
+
+    #[test]
+    fn large_cue_joins_every_line_once() {
+        let lines: Vec<String> = (1..=20_000).map(|n| format!("  line {}  ", n)).collect();
+        let srt = format!("1\n00:00:01,000 --> 03:00:00,000\n{}\n", lines.join("\n"));
+        let cues = parse_srt(&srt, None);
+        assert_eq!(cues.len(), 1);
+        let expected: Vec<String> = (1..=20_000).map(|n| format!("line {}", n)).collect();
+        assert_eq!(cues[0].text, expected.join(" "));
+        assert_eq!(cues[0].end_ms, 3 * 3600 * 1000);
+    }
+
+    #[test]
+    fn fix_timing_clamps_and_reports_out_of_order_and_overlapping_cues() {
+        let srt = "1\n00:00:05,000 --> 00:00:04,000\nBackwards.\n\n\
+                   2\n00:00:06,000 --> 00:00:08,000\nFirst.\n\n\
+                   3\n00:00:07,000 --> 00:00:09,000\nOverlapping.\n\n\
+                   4\n00:00:01,000 --> 00:00:02,000\nOut of order.\n";
+        let mut cues = parse_srt(srt, None);
+        let warnings = fix_cue_timing(&mut cues, Path::new("talk.srt"), false);
+        assert_eq!((cues[0].start_ms, cues[0].end_ms), (5000, 5000));
+        assert_eq!(
+            warnings,
+            [
+                "talk.srt: cue 1 ends before it starts; its end time is set to its start time",
+                "talk.srt: cue 3 starts before the previous cue ends",
+                "talk.srt: cue 4 starts before the previous cue (use --sort-cues to reorder)",
+            ]
+        );
+    }
+
+    #[test]
+    fn converting_two_files_into_one_directory_keeps_both() {
+        let dir = subtitle_dir(&[("a.srt", SRT), ("b.srt", "1\n00:00:05,000 --> 00:00:06,000\nAnother file.\n")]);
+        let output = dir.path().join("converted");
+        for name in ["a.srt", "b.srt"] {
+            let input = dir.path().join(name);
+            let args = ["subtitle_file_to_txt", input.to_str().unwrap(), output.to_str().unwrap()];
+            run(args.into_iter().map(String::from)).unwrap();
+        }
+        assert!(fs::read_to_string(output.join("a.txt")).unwrap().contains("Hello there."));
+        assert!(fs::read_to_string(output.join("b.txt")).unwrap().contains("Another file."));
+    }
+
+    #[test]
+    fn microdvd_frames_are_converted_at_the_given_frame_rate() {
+        let dir = subtitle_dir(&[("episode.sub", "{25}{50}Hello|there.\n{75}{110}{y:i}General Kenobi.\n")]);
+        let cue_options = CueOptions { fps: Some(25.0), ..CueOptions::default() };
+        let cues = parse_subtitles(&dir.path().join("episode.sub"), cue_options).unwrap();
+        let times: Vec<(u64, u64, &str)> = cues.iter().map(|cue| (cue.start_ms, cue.end_ms, cue.text.as_str())).collect();
+        assert_eq!(times, [(1000, 2000, "Hello there."), (3000, 4400, "General Kenobi.")]);
+    }
+}
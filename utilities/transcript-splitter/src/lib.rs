@@ -0,0 +1,549 @@
+// Author: Mohammed H Alsaeygh
+// Project: Transcript Splitter
+//
+// Description:
+// The splitting logic of the transcript splitter, shared with other tools (e.g. subtitle_file_to_txt --split)
+// so they can turn transcript text held in memory into part files without writing an intermediate file.
+
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+// Default part filename template, e.g. transcript_part_001.txt
+pub const DEFAULT_NAME_TEMPLATE: &str = "{stem}_part_{index}{ext}";
+
+// Name of the checksum manifest written next to the parts, in sha256sum format
+pub const CHECKSUM_MANIFEST_NAME: &str = "checksums.sha256";
+
+// Suffix of the single file written with PartFormat::Json, e.g. transcript_parts.json
+pub const JSON_PARTS_SUFFIX: &str = "_parts.json";
+
+// Header and footer wrapped around every part; both default to empty, so plain splitting needs no
+// configuration and a configuration may give only one of them
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub header: String,
+    pub footer: String,
+}
+
+// Options controlling how transcript text is split into part files
+pub struct SplitOptions<'a> {
+    pub max_tokens_per_split: usize,
+    pub continuation_marker: bool,
+    pub name_template: &'a str,
+    pub line_ending: LineEnding,
+    // Split at each occurrence of this string, one part per section, ignoring the token budget
+    pub delimiter: Option<&'a str>,
+    // Keep fenced code blocks and markdown tables whole, moving a block that does not fit to the next part
+    pub respect_markdown: bool,
+    pub format: PartFormat,
+}
+
+// How the parts are written to the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PartFormat {
+    // One text file per part, named by the name template
+    #[default]
+    Text,
+    // A single <stem>_parts.json holding [{"index": 1, "text": ...}, ...], for APIs taking an array of chunks
+    Json,
+}
+
+// Newlines written in part files
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    // Text is written as it is, with the "\n" newlines the splitter itself adds
+    #[default]
+    Lf,
+    // Every newline is written as "\r\n", for Windows tools
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            LineEnding::Lf => text.to_string(),
+            LineEnding::Crlf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+        }
+    }
+}
+
+// Unicode normalization form applied to text before its tokens are counted or it is sent to an LLM, so composed
+// and decomposed accents are treated alike
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnicodeForm {
+    // Canonical composition: "e" followed by a combining accent becomes "é"
+    Nfc,
+    // Compatibility composition: additionally folds ligatures, full-width forms and the like, e.g. "ﬁ" to "fi"
+    Nfkc,
+}
+
+impl UnicodeForm {
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            UnicodeForm::Nfc => text.nfc().collect(),
+            UnicodeForm::Nfkc => text.nfkc().collect(),
+        }
+    }
+}
+
+impl FromStr for UnicodeForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nfc" => Ok(UnicodeForm::Nfc),
+            "nfkc" => Ok(UnicodeForm::Nfkc),
+            _ => Err(format!("Invalid Unicode normalization form: {}", s)),
+        }
+    }
+}
+
+// Outcome of splitting content into part files
+pub struct SplitResult {
+    // Filename of each part in the output directory, or of the single JSON file with PartFormat::Json
+    pub part_names: Vec<String>,
+    // Number of tokens in each part
+    pub part_sizes: Vec<usize>,
+    // Parts written because they were new or their content changed
+    pub rewritten: usize,
+    // Parts left untouched because the file on disk already had the same content
+    pub unchanged: usize,
+}
+
+pub fn read_config(config_file: Option<&str>, config_inline: Option<&str>) -> io::Result<Config> {
+    let (config_content, source) = match (config_inline, config_file) {
+        (Some(inline), _) => (inline.to_string(), "--config-inline"),
+        (None, Some("-")) => {
+            // Read the configuration JSON from stdin
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content)?;
+            (content, "stdin")
+        }
+        (None, Some(file)) => (read_text_file(file)?, file),
+        // Without any configuration, parts are written without a header or footer
+        (None, None) => return Ok(Config::default()),
+    };
+    // Name the source and the line and column of a syntax error, e.g. a trailing comma
+    serde_json::from_str(strip_bom(&config_content)).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid configuration JSON in {}: {}", source, e))
+    })
+}
+
+// Drop a leading UTF-8 byte order mark, as written by some Windows tools
+pub fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{feff}').unwrap_or(text)
+}
+
+pub fn read_text_file(file_path: &str) -> io::Result<String> {
+    let content = fs::read_to_string(file_path)?;
+    Ok(strip_bom(&content).to_string())
+}
+
+// Like read_text_file, but replacing invalid UTF-8 with U+FFFD instead of failing
+pub fn read_text_file_lossy(file_path: &str) -> io::Result<String> {
+    let bytes = fs::read(file_path)?;
+    Ok(strip_bom(&String::from_utf8_lossy(&bytes)).to_string())
+}
+
+pub fn wrap_with_header_footer(text: &str, config: &Config) -> String {
+    format!("{}{}{}\n\n", config.header, text, config.footer)
+}
+
+// Turn transcript content into a single line of script text
+pub fn transcript_text(content: &str) -> String {
+    // Skip header lines (Start Time:, End Time:)
+    let mut text_lines: Vec<String> = content
+        .lines()
+        .filter(|line| !line.starts_with("Start Time:") && !line.starts_with("End Time:"))
+        .map(|line| line.trim().to_string())
+        .collect();
+
+    // Keep "Script: " lines and remove only the prefix
+    text_lines.iter_mut().for_each(|line| {
+        if line.starts_with("Script: ") {
+            *line = line.replacen("Script: ", "", 1);
+        }
+    });
+
+    // Join the lines with spaces
+    text_lines.join(" ")
+}
+
+// Split transcript content into part files named after `stem` and `extension` in the output directory.
+// Parts whose file already holds the same content are not rewritten, so re-splitting an edited transcript
+// only touches the parts that changed. With PartFormat::Json the parts are written to a single
+// <stem>_parts.json instead.
+pub fn split_content(
+    content: &str,
+    stem: &str,
+    extension: &str,
+    config: &Config,
+    output_dir: &str,
+    options: &SplitOptions,
+) -> io::Result<SplitResult> {
+    let parts = match options.delimiter {
+        Some(delimiter) => split_sections(content, delimiter),
+        None if options.respect_markdown => split_markdown(content, options.max_tokens_per_split),
+        None => split_tokens(&transcript_text(content), options.max_tokens_per_split)?,
+    };
+    let num_splits = parts.len();
+    let part_sizes: Vec<usize> = parts.iter().map(|part| part.split_whitespace().count()).collect();
+
+    let mut part_texts = Vec::with_capacity(num_splits);
+    let mut continues_previous = false;
+    for (i, part) in parts.into_iter().enumerate() {
+        let mut part_body = part;
+
+        // Signal to the LLM that a part starts or ends in the middle of a sentence
+        if options.continuation_marker {
+            let is_last = i + 1 == num_splits;
+            let ends_mid_sentence = !is_last && !ends_sentence(&part_body);
+            if continues_previous {
+                part_body = format!("...{}", part_body);
+            }
+            if ends_mid_sentence {
+                part_body.push_str("...");
+            }
+            continues_previous = ends_mid_sentence;
+        }
+
+        part_texts.push(options.line_ending.apply(&wrap_with_header_footer(&part_body, config)));
+    }
+
+    // With PartFormat::Json every part goes into one array, otherwise each part gets its own file
+    let files = match options.format {
+        PartFormat::Text => {
+            let part_names = part_file_names(options.name_template, stem, extension, num_splits)?;
+            part_names.into_iter().zip(part_texts).collect()
+        }
+        PartFormat::Json => {
+            let entries: Vec<serde_json::Value> = part_texts
+                .into_iter()
+                .enumerate()
+                .map(|(i, text)| json!({"index": i + 1, "text": text}))
+                .collect();
+            let json_text = serde_json::to_string_pretty(&entries)?;
+            vec![(format!("{}{}", stem, JSON_PARTS_SUFFIX), json_text)]
+        }
+    };
+
+    // Create the output directory
+    fs::create_dir_all(output_dir)?;
+
+    let mut result = SplitResult {
+        part_names: Vec::with_capacity(files.len()),
+        part_sizes,
+        rewritten: 0,
+        unchanged: 0,
+    };
+    for (file_name, file_text) in files {
+        let output_file = format!("{}/{}", output_dir, file_name);
+        result.part_names.push(file_name);
+        if fs::read(&output_file).is_ok_and(|existing| existing == file_text.as_bytes()) {
+            result.unchanged += 1;
+            continue;
+        }
+        let mut output = File::create(output_file)?;
+        output.write_all(file_text.as_bytes())?;
+        result.rewritten += 1;
+    }
+
+    Ok(result)
+}
+
+// Write the SHA-256 of each part file to a checksum manifest in the output directory, one
+// "<sha256>  <filename>" line per part as written by sha256sum, so the summarization CLIs
+// (or sha256sum -c) can verify the parts before use. Each of `comments` is written first as a
+// "# <comment>" line, which sha256sum and the summarization CLIs skip
+pub fn write_checksum_manifest(
+    output_dir: &str,
+    part_names: &[String],
+    comments: &[String],
+) -> io::Result<()> {
+    let mut manifest = String::new();
+    for comment in comments {
+        manifest.push_str(&format!("# {}\n", comment));
+    }
+    for part_name in part_names {
+        let content = fs::read(format!("{}/{}", output_dir, part_name))?;
+        manifest.push_str(&format!("{:x}  {}\n", Sha256::digest(&content), part_name));
+    }
+    fs::write(format!("{}/{}", output_dir, CHECKSUM_MANIFEST_NAME), manifest)
+}
+
+// Split text into parts of at most `max_tokens_per_split` whitespace-separated tokens, joined with single
+// spaces; empty text gives a single empty part. A limit of 0 tokens is rejected as InvalidInput.
+pub fn split_tokens(text: &str, max_tokens_per_split: usize) -> io::Result<Vec<String>> {
+    if max_tokens_per_split == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Max tokens per split must be at least 1"));
+    }
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Ok(vec![String::new()]);
+    }
+    Ok(tokens
+        .chunks(max_tokens_per_split)
+        .map(|part_tokens| part_tokens.join(" "))
+        .collect())
+}
+
+// Split transcript content into parts of at most `max_tokens_per_split` tokens like split_tokens, but keeping each
+// fenced code block (``` or ~~~) and markdown table (consecutive lines starting with "|") whole with its line
+// breaks. A block that does not fit in the current part starts the next one; a block longer than the budget
+// becomes a part of its own. Content without any text gives a single empty part.
+pub fn split_markdown(content: &str, max_tokens_per_split: usize) -> Vec<String> {
+    // The words of the transcript text and its blocks, in order, each flagged whether it is a block
+    let mut units: Vec<(String, bool)> = Vec::new();
+    let mut lines = content
+        .lines()
+        .filter(|line| !line.starts_with("Start Time:") && !line.starts_with("End Time:"))
+        .map(|line| {
+            let line = line.trim_end();
+            line.trim_start().strip_prefix("Script: ").unwrap_or(line)
+        })
+        .peekable();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        if let Some(fence) = ["```", "~~~"].into_iter().find(|fence| trimmed.starts_with(fence)) {
+            // The block runs to the closing fence, or to the end of an unterminated block
+            let mut block = vec![line];
+            for line in lines.by_ref() {
+                block.push(line);
+                if line.trim_start().starts_with(fence) {
+                    break;
+                }
+            }
+            units.push((block.join("\n"), true));
+        } else if trimmed.starts_with('|') {
+            let mut block = vec![trimmed];
+            while let Some(line) = lines.next_if(|line| line.trim_start().starts_with('|')) {
+                block.push(line.trim_start());
+            }
+            units.push((block.join("\n"), true));
+        } else {
+            units.extend(line.split_whitespace().map(|word| (word.to_string(), false)));
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut part_tokens = 0;
+    let mut after_block = false;
+    for (unit, is_block) in units {
+        let tokens = unit.split_whitespace().count();
+        if part_tokens > 0 && part_tokens + tokens > max_tokens_per_split {
+            parts.push(std::mem::take(&mut part));
+            part_tokens = 0;
+        }
+        // Blocks sit on lines of their own, words are joined with single spaces
+        if !part.is_empty() {
+            part.push(if is_block || after_block { '\n' } else { ' ' });
+        }
+        part.push_str(&unit);
+        part_tokens += tokens;
+        after_block = is_block;
+    }
+    if !part.is_empty() || parts.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+// Split transcript content at each occurrence of `delimiter`, turning every non-empty section into a part of
+// single-space-joined tokens; content without any text gives a single empty part
+pub fn split_sections(content: &str, delimiter: &str) -> Vec<String> {
+    let sections: Vec<String> = content
+        .split(delimiter)
+        .map(|section| transcript_text(section).split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|section| !section.is_empty())
+        .collect();
+    if sections.is_empty() {
+        return vec![String::new()];
+    }
+    sections
+}
+
+// Render the filename of every part from the template, rejecting templates that produce duplicate names
+pub fn part_file_names(template: &str, stem: &str, extension: &str, total: usize) -> io::Result<Vec<String>> {
+    let names: Vec<String> = (1..=total)
+        .map(|index| {
+            template
+                .replace("{stem}", stem)
+                // Pad the index with zeros to ensure it has three digits
+                .replace("{index}", &format!("{:03}", index))
+                .replace("{ext}", extension)
+                .replace("{total}", &total.to_string())
+        })
+        .collect();
+
+    let unique: HashSet<&String> = names.iter().collect();
+    if unique.len() != names.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Name template '{}' does not produce unique part filenames (use {{index}})", template),
+        ));
+    }
+    Ok(names)
+}
+
+// Whether text ends with sentence-ending punctuation, ignoring closing quotes and brackets
+fn ends_sentence(text: &str) -> bool {
+    text.trim_end()
+        .trim_end_matches(['"', '\'', ')', ']'])
+        .ends_with(['.', '!', '?'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_config_sets_header_and_footer() {
+        let config = read_config(None, Some(r#"{"header":"X","footer":"Y"}"#)).unwrap();
+        assert_eq!(config.header, "X");
+        assert_eq!(config.footer, "Y");
+        assert_eq!(wrap_with_header_footer("text", &config), "XtextY\n\n");
+    }
+
+    #[test]
+    fn inline_config_takes_precedence_over_config_file() {
+        let config = read_config(Some("missing.json"), Some(r#"{"header":"X"}"#)).unwrap();
+        assert_eq!(config.header, "X");
+        assert_eq!(config.footer, "");
+    }
+
+    #[test]
+    fn missing_config_defaults_to_empty_header_and_footer() {
+        let config = read_config(None, None).unwrap();
+        assert_eq!(config.header, "");
+        assert_eq!(config.footer, "");
+        assert_eq!(wrap_with_header_footer("text", &config), "text\n\n");
+    }
+
+    #[test]
+    fn invalid_inline_config_names_its_source() {
+        let error = read_config(None, Some(r#"{"header":"X",}"#)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("--config-inline"));
+    }
+
+    // Split content with the given options into a fresh directory, returning the part texts in order
+    fn split_parts(content: &str, config: &Config, options: &SplitOptions) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        let result = split_content(content, "talk", ".txt", config, output_dir, options).unwrap();
+        result
+            .part_names
+            .iter()
+            .map(|name| fs::read_to_string(dir.path().join(name)).unwrap())
+            .collect()
+    }
+
+    fn token_options(max_tokens_per_split: usize) -> SplitOptions<'static> {
+        SplitOptions {
+            max_tokens_per_split,
+            continuation_marker: false,
+            name_template: DEFAULT_NAME_TEMPLATE,
+            line_ending: LineEnding::Lf,
+            delimiter: None,
+            respect_markdown: false,
+            format: PartFormat::Text,
+        }
+    }
+
+    #[test]
+    fn continuation_marker_marks_only_mid_sentence_splits() {
+        let options = SplitOptions {
+            continuation_marker: true,
+            ..token_options(4)
+        };
+        let parts = split_parts("One two three four five six. Seven eight nine.", &Config::default(), &options);
+        assert_eq!(
+            parts,
+            ["One two three four...\n\n", "...five six. Seven eight...\n\n", "...nine.\n\n"]
+        );
+
+        let parts = split_parts("One two three four. Five six seven.", &Config::default(), &options);
+        assert_eq!(parts, ["One two three four.\n\n", "Five six seven.\n\n"]);
+    }
+
+    #[test]
+    fn parts_have_no_markers_by_default() {
+        let parts = split_parts("One two three four five six.", &Config::default(), &token_options(4));
+        assert_eq!(parts, ["One two three four\n\n", "five six.\n\n"]);
+    }
+
+    #[test]
+    fn name_template_sets_the_part_filenames() {
+        let names = part_file_names("{index}-{stem}-of-{total}{ext}", "talk", ".txt", 3).unwrap();
+        assert_eq!(names, ["001-talk-of-3.txt", "002-talk-of-3.txt", "003-talk-of-3.txt"]);
+
+        let options = SplitOptions {
+            name_template: "{index}-{stem}{ext}",
+            ..token_options(2)
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let result =
+            split_content("a b c", "talk", ".md", &Config::default(), dir.path().to_str().unwrap(), &options).unwrap();
+        assert_eq!(result.part_names, ["001-talk.md", "002-talk.md"]);
+        assert!(dir.path().join("002-talk.md").is_file());
+    }
+
+    #[test]
+    fn name_template_without_index_is_rejected() {
+        let error = part_file_names("{stem}{ext}", "talk", ".txt", 2).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("'{stem}{ext}' does not produce unique part filenames"));
+        // A single part is unique whatever the template
+        assert_eq!(part_file_names("{stem}{ext}", "talk", ".txt", 1).unwrap(), ["talk.txt"]);
+    }
+
+    #[test]
+    fn split_tokens_rejects_a_zero_token_limit() {
+        assert_eq!(split_tokens("a b c", 0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(split_tokens("a b c", 2).unwrap(), ["a b", "c"]);
+        assert_eq!(split_tokens("  ", 2).unwrap(), [""]);
+    }
+
+    #[test]
+    fn byte_order_mark_is_stripped_from_inputs_and_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let transcript = dir.path().join("talk.txt");
+        fs::write(&transcript, "\u{feff}Script: First line.\n").unwrap();
+        let transcript = transcript.to_str().unwrap();
+        assert_eq!(read_text_file(transcript).unwrap(), "Script: First line.\n");
+        assert_eq!(read_text_file_lossy(transcript).unwrap(), "Script: First line.\n");
+
+        let config_file = dir.path().join("config.json");
+        fs::write(&config_file, "\u{feff}{\"header\":\"X\"}").unwrap();
+        assert_eq!(read_config(config_file.to_str(), None).unwrap().header, "X");
+    }
+
+    #[test]
+    fn unchanged_parts_are_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        let split = |content: &str| {
+            split_content(content, "talk", ".txt", &Config::default(), output_dir, &token_options(2)).unwrap()
+        };
+        let first = split("one two three four five");
+        assert_eq!((first.rewritten, first.unchanged), (3, 0));
+        let modified = |name: &str| fs::metadata(dir.path().join(name)).unwrap().modified().unwrap();
+        let before: Vec<_> = first.part_names.iter().map(|name| modified(name)).collect();
+
+        let again = split("one two three four five");
+        assert_eq!((again.rewritten, again.unchanged), (0, 3));
+        let after: Vec<_> = again.part_names.iter().map(|name| modified(name)).collect();
+        assert_eq!(after, before);
+
+        let edited = split("one two THREE four five");
+        assert_eq!((edited.rewritten, edited.unchanged), (1, 2));
+    }
+}
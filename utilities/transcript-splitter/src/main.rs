@@ -7,53 +7,108 @@
 // How to Use:
 // - Compile the code using the Rust compiler.
 // - Run the executable with the following command-line arguments:
-//   - -i <input_file>: Path to the input transcript file.
+//   - -i <input_file>: Path to the input transcript file, or a quoted glob pattern (e.g. 'transcripts/*_en.txt') to split
+//     every matching file. With a pattern, each file is written to its own <name>_splits directory, inside -o if given.
 //   - -o <output_dir>: Optional. Output directory for split files. If not provided, splits will be saved in a directory named after the input file in the current directory.
 //   - -s <max_tokens_per_split>: Maximum number of tokens (words) per split.
-//   - -c <config_file>: Path to the configuration file specifying header and footer content.
+//   - --budget <percent>% --context-window <tokens>: Optional, instead of -s. Sets the maximum tokens per split to
+//     this percentage of the model's context window, e.g. --budget 75% --context-window 4096 for 3072 tokens, leaving
+//     room for the prompt template and the summary.
+//   - --split-on <tokens|delimiter>: Optional. "tokens" (default) splits by -s; "delimiter" splits at each occurrence
+//     of the --delimiter <text> string instead, writing one part per non-empty section (-s is then not needed).
+//   - -c <config_file>: Optional. Path to the configuration file specifying header and footer content, or "-" to read it from stdin.
+//   - --config-inline <json>: Optional. Configuration JSON passed directly, e.g. '{"header":"X","footer":"Y"}'.
+//     When neither is given, a config.json next to the input file or in the current directory is used if present;
+//     otherwise the header and footer are empty.
+//   - --no-auto-config: Optional flag that disables looking for a config.json when no configuration is given.
+//   - --validate-json: Optional flag that parses the configuration (given or discovered for each input file) and
+//     exits without splitting, reporting the line and column of a syntax error.
 //   - --single-shot: Optional flag to enable single shot mode, which generates a single output file for the entire transcript.
+//   - --name-template <template>: Optional. Template for part filenames using the placeholders {stem}, {index} (zero-padded),
+//     {ext} (including the leading dot) and {total}. Defaults to "{stem}_part_{index}{ext}".
+//   - --format <text|json>: Optional. "text" (default) writes one file per part; "json" writes all parts to a single
+//     <stem>_parts.json array of {"index": n, "text": ...} objects, for APIs that take an array of chunks.
+//   - --respect-markdown: Optional flag for token splitting that keeps fenced code blocks and markdown tables whole,
+//     with their line breaks, moving a block that does not fit in a part to the next part.
+//   - --continuation-marker: Optional flag that marks parts split mid-sentence with a trailing "..." and starts the next part with "...".
+//   - Parts whose file already has the same content are left untouched, so re-splitting an edited transcript only
+//     rewrites the parts that changed; the number of rewritten and unchanged parts is printed for each input file.
+//   - --checksums: Optional flag for split mode that also writes checksums.sha256 to the output directory, with the
+//     SHA-256 of every part, for the summarization CLIs' --checksum-manifest verification.
+//   - --with-metadata: Optional flag for --checksums that starts the manifest with "# _run_id: <id>" and
+//     "# _timestamp: <time>" comment lines, to correlate the parts with the other tools' outputs.
+//   - --run-id <id>: Optional. Run ID written with --with-metadata. Defaults to a random UUID.
+//   - --line-ending <lf|crlf>: Optional. Newlines written in the part files and single-shot output. Defaults to "lf";
+//     "crlf" writes Windows line endings, including in the header and footer.
+//   - --json-summary: Optional flag that prints a JSON line per input file to stderr with the input path, mode
+//     ("split" or "single_shot"), number of parts, total tokens and output directory, for wrapper scripts.
+//   - --normalize-unicode <nfc|nfkc>: Optional. Normalize the input text to this Unicode form before counting
+//     tokens and writing the parts, so composed and decomposed accents are counted and written alike.
+//   - --replace <regex>=<replacement>: Optional, repeatable. Replace every match of the regex in the input text
+//     before counting tokens and splitting, e.g. to scrub names or fix recurring transcription errors. The
+//     replacement may refer to capture groups as $1 or ${name}; write \= for a literal "=" in the regex.
+//   - --lossy-utf8: Optional flag that reads input files with invalid UTF-8 by replacing the bad bytes with U+FFFD
+//     instead of failing; by default such a file is an error.
+//   - --report: Optional flag that prints the min/max/mean/median token counts of the parts and an ASCII bar per part.
 //
 // Example Usage:
 // $ ./transcript_splitter -i input.txt -o output_directory -s 1000 -c config.json
 // $ ./transcript_splitter -i input.txt -c config.json --single-shot
+// $ ./transcript_splitter -i 'transcripts/*_en.txt' -o output_directory -s 1000 -c config.json
+// $ ./transcript_splitter -i input.txt -s 1000 --config-inline '{"header":"Summarize:\n","footer":""}'
+// $ ./transcript_splitter -i input.txt --split-on delimiter --delimiter '=== SECTION ==='
+//
+// The splitting logic lives in the library part of this crate (src/lib.rs) so other tools, such as
+// subtitle_file_to_txt --split, can split transcript text without writing an intermediate file.
 //
 // Dependencies:
 // - serde: For JSON deserialization.
+// - glob: For expanding input file patterns.
+// - sha2: For the --checksums manifest.
+// - uuid, humantime: For the --with-metadata run ID and timestamp.
+// - unicode-normalization: For --normalize-unicode.
+// - regex: For --replace.
 // - std: Standard Rust library for file I/O and command-line argument parsing.
 //
 // This tool simplifies the process of handling and processing transcript data, facilitating easier management and manipulation of large transcript files.
 
-use serde::Deserialize;
 use std::env;
 use std::fs::{self, File};
-use std::io::{self, BufRead, Write};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use regex::Regex;
+use serde_json::json;
+use transcript_splitter::{
+    read_config, read_text_file, read_text_file_lossy, split_content, wrap_with_header_footer, write_checksum_manifest,
+    Config, LineEnding, PartFormat, SplitOptions, UnicodeForm, SplitResult, CHECKSUM_MANIFEST_NAME, DEFAULT_NAME_TEMPLATE,
+};
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    header: String,
-    footer: String,
-}
+// Configuration file picked up automatically when -c and --config-inline are not given
+const AUTO_CONFIG_FILE: &str = "config.json";
 
-fn read_config(config_file: &str) -> io::Result<Config> {
-    let config_content = fs::read_to_string(config_file)?;
-    let config: Config = serde_json::from_str(&config_content)?;
-    Ok(config)
-}
+// Width of the longest bar in the --report output
+const REPORT_BAR_WIDTH: usize = 40;
 
-fn wrap_with_header_footer(text: &str, config: &Config) -> String {
-    format!("{}{}{}\n\n", config.header, text, config.footer)
+// Read an input file, strictly or (with --lossy-utf8) replacing invalid UTF-8
+fn read_input(input_file: &str, lossy_utf8: bool) -> io::Result<String> {
+    if lossy_utf8 {
+        read_text_file_lossy(input_file)
+    } else {
+        read_text_file(input_file)
+    }
 }
 
+// Write the whole transcript wrapped in the header and footer, returning its number of tokens
 fn single_shot_mode(
+    file_content: &str,
     input_file: &str,
-    config_file: &str,
+    config: &Config,
     output_dir: Option<&str>,
-) -> io::Result<()> {
-    let config = read_config(config_file)?;
-
-    let file_content = fs::read_to_string(input_file)?;
-    let wrapped_text = wrap_with_header_footer(&file_content, &config);
+    line_ending: LineEnding,
+) -> io::Result<usize> {
+    let wrapped_text = line_ending.apply(&wrap_with_header_footer(file_content, config));
 
     let (file_name, file_extension) = split_extension(input_file);
 
@@ -71,39 +126,16 @@ fn single_shot_mode(
     let mut output = File::create(output_file)?;
     output.write_all(wrapped_text.as_bytes())?;
 
-    Ok(())
+    Ok(file_content.split_whitespace().count())
 }
 
 fn split_text(
+    file_content: &str,
     input_file: &str,
-    max_tokens_per_split: usize,
-    config_file: &str,
+    config: &Config,
     output_dir: Option<&str>,
-) -> io::Result<()> {
-    let file = File::open(input_file)?;
-    let reader = io::BufReader::new(file);
-    let lines: Vec<String> = reader.lines().map(|l| l.unwrap()).collect();
-
-    // Skip header lines (Start Time:, End Time:)
-    let mut text_lines: Vec<String> = lines
-        .into_iter()
-        .filter(|line| !line.starts_with("Start Time:") && !line.starts_with("End Time:"))
-        .map(|line| line.trim().to_string())
-        .collect();
-
-    // Keep "Script: " lines and remove only the prefix
-    text_lines.iter_mut().for_each(|line| {
-        if line.starts_with("Script: ") {
-            *line = line.replacen("Script: ", "", 1);
-        }
-    });
-
-    // Join the lines with spaces
-    let text = text_lines.join(" ");
-    let num_tokens = text.split_whitespace().count();
-    let num_splits = (num_tokens - 1) / max_tokens_per_split + 1;
-
-    let config = read_config(config_file)?;
+    options: &SplitOptions,
+) -> io::Result<SplitResult> {
     let (file_name, file_extension) = split_extension(input_file);
 
     // Determine the output directory
@@ -115,23 +147,82 @@ fn split_text(
         current_dir.join(format!("{}_splits", file_stem)).to_string_lossy().to_string()
     };
 
-    // Create the output directory
-    fs::create_dir_all(&output_dir)?;
+    split_content(file_content, &file_name, &file_extension, config, &output_dir, options)
+}
 
-    for i in 0..num_splits {
-        let start = i * max_tokens_per_split;
-        let part_tokens: Vec<&str> = text.split_whitespace().skip(start).take(max_tokens_per_split).collect();
-        let part_text = format!("{}{}{}\n\n", config.header, part_tokens.join(" "), config.footer);
+// Format min/max/mean/median token counts across the parts and a bar per part
+fn format_report(part_sizes: &[usize]) -> String {
+    let mut sorted = part_sizes.to_vec();
+    sorted.sort_unstable();
+    let (min, max) = (sorted[0], sorted[sorted.len() - 1]);
+    let mean = sorted.iter().sum::<usize>() as f64 / sorted.len() as f64;
+    let mid = sorted.len() / 2;
+    let median = if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) as f64 / 2.0
+    } else {
+        sorted[mid] as f64
+    };
 
-        // Pad the index with zeros to ensure it has three digits
-        let index_padded = format!("{:03}", i + 1);
+    let mut report = format!("Parts: {}\n", part_sizes.len());
+    report.push_str(&format!("Tokens: min {}, max {}, mean {:.1}, median {:.1}\n", min, max, mean, median));
+    for (i, size) in part_sizes.iter().enumerate() {
+        let bar_len = (size * REPORT_BAR_WIDTH).checked_div(max).unwrap_or(0);
+        report.push_str(&format!("{:03} {:>6} {}\n", i + 1, size, "#".repeat(bar_len)));
+    }
+    report
+}
 
-        let output_file = format!("{}/{}_part_{}{}", output_dir, file_name, index_padded, file_extension);
-        let mut output = File::create(output_file)?;
-        output.write_all(part_text.as_bytes())?;
+// Look for a config.json next to the input file, then in the current directory; without one
+// the header and footer are empty
+fn discover_config_path(input_file: &str, auto_config: bool) -> io::Result<Option<PathBuf>> {
+    if !auto_config {
+        return Ok(None);
     }
+    let input_dir = Path::new(input_file).parent().unwrap_or_else(|| Path::new(""));
+    let candidates = [input_dir.join(AUTO_CONFIG_FILE), env::current_dir()?.join(AUTO_CONFIG_FILE)];
+    Ok(candidates.into_iter().find(|candidate| candidate.is_file()))
+}
 
-    Ok(())
+// Expand an input glob pattern into the matching files
+fn expand_input_pattern(pattern: &str) -> io::Result<Vec<String>> {
+    let paths = glob::glob(pattern).map_err(|e| {
+        println!("Error: Invalid input pattern '{}': {}", pattern, e);
+        io::Error::from(io::ErrorKind::InvalidInput)
+    })?;
+    let input_files: Vec<String> = paths
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    if input_files.is_empty() {
+        println!("Error: No input files match '{}'", pattern);
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+    Ok(input_files)
+}
+
+// Output directory for an input file: the -o directory, or <stem>_splits in the current directory.
+// With an input pattern every file gets its own <stem>_splits directory, inside the -o directory if given.
+fn output_dir_for(input_file: &str, output_dir: Option<&str>, is_pattern: bool) -> String {
+    let file_stem = Path::new(input_file).file_stem().unwrap().to_string_lossy();
+    let splits_dir = format!("{}_splits", file_stem);
+    match output_dir {
+        Some(dir) if is_pattern => Path::new(dir).join(splits_dir).to_string_lossy().to_string(),
+        Some(dir) => dir.to_string(),
+        None => env::current_dir().unwrap().join(splits_dir).to_string_lossy().to_string(),
+    }
+}
+
+// Parse a --replace "<regex>=<replacement>" argument, splitting at the first "=" not escaped as "\="
+fn parse_replacement(arg: &str) -> Result<(Regex, String), String> {
+    let separator = arg
+        .char_indices()
+        .find(|&(i, c)| c == '=' && !arg[..i].ends_with('\\'))
+        .map(|(i, _)| i)
+        .ok_or_else(|| format!("Expected <regex>=<replacement> after --replace flag, got '{}'", arg))?;
+    let (pattern, replacement) = (&arg[..separator], &arg[separator + 1..]);
+    let regex = Regex::new(pattern).map_err(|e| format!("Invalid --replace regex '{}': {}", pattern, e))?;
+    Ok((regex, replacement.to_string()))
 }
 
 fn split_extension(file_path: &str) -> (String, String) {
@@ -142,12 +233,38 @@ fn split_extension(file_path: &str) -> (String, String) {
 }
 
 fn main() -> io::Result<()> {
-    let mut args = env::args().skip(1);
+    run(env::args(), &mut io::stderr())
+}
+
+// Split or wrap the input files as the command-line arguments say, the program name first. The --json-summary
+// lines are written to `stderr`.
+fn run(args: impl Iterator<Item = String>, stderr: &mut dyn Write) -> io::Result<()> {
+    let mut args = args.skip(1);
     let mut input_file = None;
     let mut output_dir = None;
     let mut max_tokens_per_split = None;
+    let mut budget_percent = None;
+    let mut context_window = None;
     let mut config_file = None;
+    let mut config_inline = None;
+    let mut auto_config = true;
+    let mut validate_json = false;
     let mut single_shot = false; // Flag for single shot mode
+    let mut continuation_marker = false;
+    let mut name_template = DEFAULT_NAME_TEMPLATE.to_string();
+    let mut report = false;
+    let mut checksums = false;
+    let mut lossy_utf8 = false;
+    let mut with_metadata = false;
+    let mut run_id = None;
+    let mut json_summary = false;
+    let mut line_ending = LineEnding::default();
+    let mut split_on_delimiter = false;
+    let mut delimiter = None;
+    let mut format = PartFormat::default();
+    let mut normalize_unicode = None;
+    let mut respect_markdown = false;
+    let mut replacements = Vec::new();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -175,6 +292,27 @@ fn main() -> io::Result<()> {
                     return Ok(());
                 }
             }
+            "--budget" => {
+                // Given as a percentage, with or without the trailing %
+                let percent = args
+                    .next()
+                    .and_then(|budget| budget.trim_end_matches('%').parse::<f64>().ok())
+                    .filter(|percent| *percent > 0.0 && *percent <= 100.0);
+                if let Some(percent) = percent {
+                    budget_percent = Some(percent);
+                } else {
+                    println!("Error: Expected a percentage between 0 and 100 after --budget flag, e.g. 75%");
+                    return Ok(());
+                }
+            }
+            "--context-window" => {
+                if let Some(tokens) = args.next().and_then(|t| t.parse::<usize>().ok()) {
+                    context_window = Some(tokens);
+                } else {
+                    println!("Error: Invalid value for context window tokens");
+                    return Ok(());
+                }
+            }
             "-c" => {
                 if let Some(file) = args.next() {
                     config_file = Some(file);
@@ -183,9 +321,114 @@ fn main() -> io::Result<()> {
                     return Ok(());
                 }
             }
+            "--config-inline" => {
+                if let Some(json) = args.next() {
+                    config_inline = Some(json);
+                } else {
+                    println!("Error: Expected configuration JSON after --config-inline flag");
+                    return Ok(());
+                }
+            }
+            "--no-auto-config" => {
+                auto_config = false;
+            }
+            "--validate-json" => {
+                validate_json = true;
+            }
             "--single-shot" => {
                 single_shot = true; // Set the single shot flag
             }
+            "--name-template" => {
+                if let Some(template) = args.next() {
+                    name_template = template;
+                } else {
+                    println!("Error: Expected template after --name-template flag");
+                    return Ok(());
+                }
+            }
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => PartFormat::Text,
+                    Some("json") => PartFormat::Json,
+                    _ => {
+                        println!("Error: Expected text or json after --format flag");
+                        return Ok(());
+                    }
+                };
+            }
+            "--respect-markdown" => {
+                respect_markdown = true;
+            }
+            "--continuation-marker" => {
+                continuation_marker = true;
+            }
+            "--checksums" => {
+                checksums = true;
+            }
+            "--with-metadata" => {
+                with_metadata = true;
+            }
+            "--run-id" => {
+                if let Some(id) = args.next() {
+                    run_id = Some(id);
+                } else {
+                    println!("Error: Expected run ID after --run-id flag");
+                    return Ok(());
+                }
+            }
+            "--line-ending" => {
+                line_ending = match args.next().as_deref() {
+                    Some("lf") => LineEnding::Lf,
+                    Some("crlf") => LineEnding::Crlf,
+                    _ => {
+                        println!("Error: Expected lf or crlf after --line-ending flag");
+                        return Ok(());
+                    }
+                };
+            }
+            "--split-on" => {
+                split_on_delimiter = match args.next().as_deref() {
+                    Some("tokens") => false,
+                    Some("delimiter") => true,
+                    _ => {
+                        println!("Error: Expected tokens or delimiter after --split-on flag");
+                        return Ok(());
+                    }
+                };
+            }
+            "--delimiter" => {
+                if let Some(text) = args.next().filter(|text| !text.is_empty()) {
+                    delimiter = Some(text);
+                } else {
+                    println!("Error: Expected a non-empty delimiter after --delimiter flag");
+                    return Ok(());
+                }
+            }
+            "--json-summary" => {
+                json_summary = true;
+            }
+            "--normalize-unicode" => {
+                if let Some(form) = args.next().and_then(|form| form.parse::<UnicodeForm>().ok()) {
+                    normalize_unicode = Some(form);
+                } else {
+                    println!("Error: Expected nfc or nfkc after --normalize-unicode flag");
+                    return Ok(());
+                }
+            }
+            "--replace" => {
+                let Some(arg) = args.next() else {
+                    println!("Error: Expected <regex>=<replacement> after --replace flag");
+                    return Ok(());
+                };
+                let replacement = parse_replacement(&arg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                replacements.push(replacement);
+            }
+            "--lossy-utf8" => {
+                lossy_utf8 = true;
+            }
+            "--report" => {
+                report = true;
+            }
             _ => {
                 println!("Error: Invalid flag '{}'", arg);
                 return Ok(());
@@ -193,48 +436,653 @@ fn main() -> io::Result<()> {
         }
     }
 
-    if single_shot {
-        let input_file = input_file.ok_or_else(|| {
-            println!("Error: Missing input file argument (-i)");
-            io::Error::from(io::ErrorKind::InvalidInput)
-        })?;
+    // An explicit configuration is read once; otherwise each input file may pick up a config.json
+    let explicit_config = if config_file.is_some() || config_inline.is_some() {
+        Some(read_config(config_file.as_deref(), config_inline.as_deref())?)
+    } else {
+        None
+    };
 
-        let config_file = config_file.ok_or_else(|| {
-            println!("Error: Missing config file argument (-c)");
-            io::Error::from(io::ErrorKind::InvalidInput)
-        })?;
+    let input_file = input_file.ok_or_else(|| {
+        println!("Error: Missing input file argument (-i)");
+        io::Error::from(io::ErrorKind::InvalidInput)
+    })?;
+
+    // A glob pattern such as "transcripts/*_en.txt" splits every matching file into its own directory. An existing
+    // file is taken literally, so a name like "Talk [2024].txt" is not read as a pattern.
+    let is_pattern = !Path::new(&input_file).is_file() && input_file.contains(['*', '?', '[']);
+    let input_files = if is_pattern {
+        expand_input_pattern(&input_file)?
+    } else {
+        vec![input_file]
+    };
+
+    // Each discovered config.json is parsed once, before any file is split, so a typo stops the run up front
+    let mut config_paths = Vec::new();
+    let mut discovered_configs: BTreeMap<PathBuf, Config> = BTreeMap::new();
+    for input_file in &input_files {
+        let config_path = match explicit_config {
+            Some(_) => None,
+            None => discover_config_path(input_file, auto_config)?,
+        };
+        if let Some(path) = config_path.as_ref().filter(|path| !discovered_configs.contains_key(*path)) {
+            println!("Using configuration {}", path.display());
+            let config = read_config(Some(&path.to_string_lossy()), None)?;
+            discovered_configs.insert(path.clone(), config);
+        }
+        config_paths.push(config_path);
+    }
+    let default_config = Config::default();
 
-        let output_dir = output_dir.unwrap_or_else(|| {
-            let current_dir = env::current_dir().unwrap();
-            let file_stem = Path::new(&input_file).file_stem().unwrap().to_string_lossy();
-            current_dir.join(format!("{}_splits", file_stem)).to_string_lossy().to_string()
-        });
+    if validate_json {
+        match (&config_inline, config_file.as_deref()) {
+            (Some(_), _) => println!("Valid JSON: --config-inline"),
+            (None, Some("-")) => println!("Valid JSON: stdin"),
+            (None, Some(file)) => println!("Valid JSON: {}", file),
+            (None, None) if discovered_configs.is_empty() => println!("No JSON configuration to validate"),
+            (None, None) => {}
+        }
+        for path in discovered_configs.keys() {
+            println!("Valid JSON: {}", path.display());
+        }
+        return Ok(());
+    }
 
-        single_shot_mode(&input_file, &config_file, Some(&output_dir))?;
+    // Only --split-on delimiter uses the delimiter
+    let delimiter = if split_on_delimiter {
+        if single_shot {
+            println!("Error: --split-on delimiter cannot be combined with --single-shot");
+            return Ok(());
+        }
+        let Some(delimiter) = delimiter else {
+            println!("Error: --split-on delimiter requires --delimiter <text>");
+            return Ok(());
+        };
+        Some(delimiter)
     } else {
-        let input_file = input_file.ok_or_else(|| {
-            println!("Error: Missing input file argument (-i)");
-            io::Error::from(io::ErrorKind::InvalidInput)
-        })?;
+        None
+    };
+
+    // Only token splitting breaks text up mid-block
+    if respect_markdown && (single_shot || split_on_delimiter) {
+        println!("Error: --respect-markdown cannot be combined with --single-shot or --split-on delimiter");
+        return Ok(());
+    }
 
-        let output_dir = output_dir.unwrap_or_else(|| {
-            let current_dir = env::current_dir().unwrap();
-            let file_stem = Path::new(&input_file).file_stem().unwrap().to_string_lossy();
-            current_dir.join(format!("{}_splits", file_stem)).to_string_lossy().to_string()
-        });
+    // The single-shot output is already one file
+    if single_shot && format == PartFormat::Json {
+        println!("Error: --format json cannot be combined with --single-shot");
+        return Ok(());
+    }
+
+    // The run metadata is written into the checksum manifests, the same for every input file
+    let manifest_comments = if with_metadata {
+        if !checksums || single_shot {
+            println!("Error: --with-metadata requires --checksums in split mode");
+            return Ok(());
+        }
+        let run_id = run_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        vec![
+            format!("_run_id: {}", run_id),
+            format!("_timestamp: {}", humantime::format_rfc3339_seconds(SystemTime::now())),
+        ]
+    } else {
+        if run_id.is_some() {
+            println!("Error: --run-id requires --with-metadata");
+            return Ok(());
+        }
+        Vec::new()
+    };
+
+    // A budget relative to the model's context window stands in for -s
+    match (budget_percent, context_window) {
+        (Some(_), _) | (_, Some(_)) if max_tokens_per_split.is_some() => {
+            println!("Error: --budget and --context-window cannot be combined with -s");
+            return Ok(());
+        }
+        (Some(percent), Some(context_window)) => {
+            let tokens = (context_window as f64 * percent / 100.0).floor() as usize;
+            if tokens == 0 {
+                println!(
+                    "Error: --budget {}% of a {} token context window leaves no tokens per split",
+                    percent, context_window
+                );
+                return Ok(());
+            }
+            println!("Token budget: {} tokens per split ({}% of {})", tokens, percent, context_window);
+            max_tokens_per_split = Some(tokens);
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            println!("Error: --budget and --context-window must be given together");
+            return Ok(());
+        }
+        (None, None) => {}
+    }
 
-        let max_tokens_per_split = max_tokens_per_split.ok_or_else(|| {
+    let max_tokens_per_split = if single_shot {
+        None
+    } else if delimiter.is_some() {
+        // Sections are never split further, so the token budget is not used
+        Some(max_tokens_per_split.unwrap_or(0))
+    } else {
+        Some(max_tokens_per_split.ok_or_else(|| {
             println!("Error: Missing max tokens per split argument (-s)");
             io::Error::from(io::ErrorKind::InvalidInput)
-        })?;
+        })?)
+    };
 
-        let config_file = config_file.ok_or_else(|| {
-            println!("Error: Missing config file argument (-c)");
-            io::Error::from(io::ErrorKind::InvalidInput)
-        })?;
+    for (input_file, config_path) in input_files.iter().zip(&config_paths) {
+        let output_dir = output_dir_for(input_file, output_dir.as_deref(), is_pattern);
+        let config = match (&explicit_config, config_path) {
+            (Some(config), _) => config,
+            (None, Some(path)) => &discovered_configs[path],
+            (None, None) => &default_config,
+        };
+
+        let mut file_content = read_input(input_file, lossy_utf8)?;
+        if let Some(form) = normalize_unicode {
+            file_content = form.apply(&file_content);
+        }
+        // Replacements apply in the order given, each to the result of the previous one
+        for (regex, replacement) in &replacements {
+            file_content = regex.replace_all(&file_content, replacement.as_str()).into_owned();
+        }
+        let (mode, parts, total_tokens) = match max_tokens_per_split {
+            None => {
+                let tokens = single_shot_mode(&file_content, input_file, config, Some(&output_dir), line_ending)?;
+                ("single_shot", 1, tokens)
+            }
+            Some(max_tokens_per_split) => {
+                let options = SplitOptions {
+                    max_tokens_per_split,
+                    continuation_marker,
+                    name_template: &name_template,
+                    line_ending,
+                    delimiter: delimiter.as_deref(),
+                    respect_markdown,
+                    format,
+                };
+                let result = match split_text(&file_content, input_file, config, Some(&output_dir), &options) {
+                    Ok(result) => result,
+                    // A name template producing duplicate names, or a budget of 0 tokens
+                    Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                        println!("Error: {}", e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
 
-        split_text(&input_file, max_tokens_per_split, &config_file, Some(&output_dir))?;
+                // Unchanged parts are not rewritten, so re-splitting only touches edited sections
+                println!(
+                    "{}: {} parts rewritten, {} unchanged",
+                    input_file, result.rewritten, result.unchanged
+                );
+
+                if checksums {
+                    write_checksum_manifest(&output_dir, &result.part_names, &manifest_comments)?;
+                    println!("Checksums written to {}/{}", output_dir, CHECKSUM_MANIFEST_NAME);
+                }
+
+                if report {
+                    print!("{}", format_report(&result.part_sizes));
+                }
+                ("split", result.part_sizes.len(), result.part_sizes.iter().sum())
+            }
+        };
+
+        // One line per input file on stderr, kept apart from the human-readable output on stdout
+        if json_summary {
+            writeln!(
+                stderr,
+                "{}",
+                json!({
+                    "input": input_file,
+                    "mode": mode,
+                    "parts": parts,
+                    "total_tokens": total_tokens,
+                    "output_dir": output_dir,
+                })
+            )?;
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_summarizes_the_part_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SplitOptions {
+            max_tokens_per_split: 100,
+            continuation_marker: false,
+            name_template: DEFAULT_NAME_TEMPLATE,
+            line_ending: LineEnding::Lf,
+            delimiter: Some("---"),
+            respect_markdown: false,
+            format: PartFormat::Text,
+        };
+        let content = "one two three\n---\nfour\n---\nfive six seven eight nine";
+        let result = split_text(content, "talk.txt", &Config::default(), dir.path().to_str(), &options).unwrap();
+        assert_eq!(result.part_sizes, vec![3, 1, 5]);
+        assert_eq!(
+            format_report(&result.part_sizes),
+            format!(
+                "Parts: 3\nTokens: min 1, max 5, mean 3.0, median 3.0\n001      3 {}\n002      1 {}\n003      5 {}\n",
+                "#".repeat(24),
+                "#".repeat(8),
+                "#".repeat(40)
+            )
+        );
+    }
+
+    // Run the splitter with the given arguments after the program name
+    fn run_splitter(args: &[&str]) -> io::Result<()> {
+        run(std::iter::once("transcript_splitter").chain(args.iter().copied()).map(String::from), &mut io::sink())
+    }
+
+    // Run the splitter like run_splitter, returning what it wrote to stderr
+    fn run_splitter_stderr(args: &[&str]) -> String {
+        let mut stderr = Vec::new();
+        run(std::iter::once("transcript_splitter").chain(args.iter().copied()).map(String::from), &mut stderr)
+            .unwrap();
+        String::from_utf8(stderr).unwrap()
+    }
+
+    #[test]
+    fn input_glob_splits_only_the_matching_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["talk_en.txt", "panel_en.txt", "talk_de.txt"] {
+            fs::write(dir.path().join(name), "one two three").unwrap();
+        }
+        let pattern = dir.path().join("*_en.txt");
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            pattern.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "2",
+            "--no-auto-config",
+        ])
+        .unwrap();
+        let mut split_dirs: Vec<String> = fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        split_dirs.sort();
+        assert_eq!(split_dirs, ["panel_en_splits", "talk_en_splits"]);
+        let part = fs::read_to_string(output.join("talk_en_splits/talk_en_part_002.txt")).unwrap();
+        assert_eq!(part, "three\n\n");
+    }
+
+    #[test]
+    fn input_file_with_brackets_is_split_as_a_literal_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("Talk [2024].txt");
+        fs::write(&input, "one two three").unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "2",
+            "--no-auto-config",
+        ])
+        .unwrap();
+        let part = fs::read_to_string(output.join("Talk [2024]_part_002.txt")).unwrap();
+        assert_eq!(part, "three\n\n");
+    }
+
+    #[test]
+    fn config_json_next_to_the_input_is_used_without_c() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two three").unwrap();
+        fs::write(dir.path().join("config.json"), r#"{"header": "Summarize:\n", "footer": "\nEnd"}"#).unwrap();
+        let output = dir.path().join("splits");
+        let split = |extra: &[&str]| {
+            let mut args = vec!["-i", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "-s", "5"];
+            args.extend(extra);
+            run_splitter(&args).unwrap();
+            fs::read_to_string(output.join("talk_part_001.txt")).unwrap()
+        };
+        assert_eq!(split(&[]), "Summarize:\none two three\nEnd\n\n");
+        assert_eq!(split(&["--no-auto-config"]), "one two three\n\n");
+    }
+
+    #[test]
+    fn splitting_without_c_writes_only_the_chunk_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "Script: one two\nStart Time: 0\nEnd Time: 900\n\nScript: three\n").unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&["-i", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "-s", "2", "--no-auto-config"])
+            .unwrap();
+        let part = |index: usize| fs::read_to_string(output.join(format!("talk_part_{:03}.txt", index))).unwrap();
+        assert_eq!([part(1), part(2)], ["one two\n\n", "three\n\n"]);
+    }
+
+    #[test]
+    fn checksums_list_the_sha256_of_every_part() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two three").unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "2",
+            "--no-auto-config",
+            "--checksums",
+            "--with-metadata",
+            "--run-id",
+            "run-42",
+        ])
+        .unwrap();
+        let manifest = fs::read_to_string(output.join(CHECKSUM_MANIFEST_NAME)).unwrap();
+        let lines: Vec<&str> = manifest.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "# _run_id: run-42");
+        assert!(lines[1].starts_with("# _timestamp: "));
+        // "one two\n\n" and "three\n\n"
+        assert_eq!(lines[2], "e4608c71857337111f14d71625aaa15680d2ef59dfe9d2781c6dd5e34cfa5902  talk_part_001.txt");
+        assert_eq!(lines[3], "00572d281bcb759780ada9f76c2d3b9c3d5c0314081f2b99ea9308520f032be9  talk_part_002.txt");
+    }
+
+    #[test]
+    fn json_summary_describes_the_split_on_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two three four five").unwrap();
+        let output = dir.path().join("splits");
+        let stderr = run_splitter_stderr(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "2",
+            "--json-summary",
+            "--no-auto-config",
+        ]);
+        let lines: Vec<&str> = stderr.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let summary: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(
+            summary,
+            json!({
+                "input": input.to_str().unwrap(),
+                "mode": "split",
+                "parts": 3,
+                "total_tokens": 5,
+                "output_dir": output.to_str().unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn line_ending_crlf_is_written_only_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two\nthree four").unwrap();
+        let wrap_config = dir.path().join("wrap.json");
+        fs::write(&wrap_config, r#"{"header": "Summarize:\n", "footer": "\nEnd"}"#).unwrap();
+        let split = |extra: &[&str]| {
+            let output = dir.path().join("splits");
+            let mut args = vec![
+                "-i",
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "-s",
+                "10",
+                "-c",
+                wrap_config.to_str().unwrap(),
+            ];
+            args.extend(extra);
+            run_splitter(&args).unwrap();
+            fs::read_to_string(output.join("talk_part_001.txt")).unwrap()
+        };
+        assert_eq!(split(&[]), "Summarize:\none two three four\nEnd\n\n");
+        assert_eq!(
+            split(&["--line-ending", "crlf"]),
+            "Summarize:\r\none two three four\r\nEnd\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn split_on_delimiter_writes_one_part_per_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(
+            &input,
+            "first section\n=== SECTION ===\nsecond section with more words\n=== SECTION ===\nthird",
+        )
+        .unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--split-on",
+            "delimiter",
+            "--delimiter",
+            "=== SECTION ===",
+            "--no-auto-config",
+        ])
+        .unwrap();
+        let mut parts: Vec<String> = fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        parts.sort();
+        assert_eq!(parts, ["talk_part_001.txt", "talk_part_002.txt", "talk_part_003.txt"]);
+        assert_eq!(fs::read_to_string(output.join("talk_part_003.txt")).unwrap(), "third\n\n");
+    }
+
+    #[test]
+    fn lossy_utf8_splits_a_file_that_strict_reading_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, b"caf\xe9 talk").unwrap();
+        let output = dir.path().join("splits");
+        let args = ["-i", input.to_str().unwrap(), "-o", output.to_str().unwrap(), "-s", "5", "--no-auto-config"];
+        let error = run_splitter(&args).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut lossy = args.to_vec();
+        lossy.push("--lossy-utf8");
+        run_splitter(&lossy).unwrap();
+        assert_eq!(fs::read_to_string(output.join("talk_part_001.txt")).unwrap(), "caf\u{fffd} talk\n\n");
+    }
+
+    #[test]
+    fn json_format_writes_one_object_per_part() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two three four five").unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "2",
+            "--format",
+            "json",
+            "--config-inline",
+            r#"{"header": "Summarize:\n"}"#,
+        ])
+        .unwrap();
+        let written: Vec<_> = fs::read_dir(&output).unwrap().collect();
+        assert_eq!(written.len(), 1);
+        let parts: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(output.join("talk_parts.json")).unwrap()).unwrap();
+        let parts = parts.as_array().unwrap();
+        assert_eq!(parts.len(), 3);
+        let indices: Vec<&serde_json::Value> = parts.iter().map(|part| &part["index"]).collect();
+        assert_eq!(indices, [&json!(1), &json!(2), &json!(3)]);
+        assert_eq!(parts[0]["text"], json!("Summarize:\none two\n\n"));
+        assert_eq!(parts[2]["text"], json!("Summarize:\nfive\n\n"));
+    }
+
+    #[test]
+    fn normalize_unicode_makes_decomposed_input_split_like_composed_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let split = |name: &str, text: &str| {
+            let input = dir.path().join(format!("{}.txt", name));
+            fs::write(&input, text).unwrap();
+            let output = dir.path().join(name);
+            let stderr = run_splitter_stderr(&[
+                "-i",
+                input.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+                "-s",
+                "2",
+                "--normalize-unicode",
+                "nfc",
+                "--json-summary",
+                "--no-auto-config",
+            ]);
+            let summary: serde_json::Value = serde_json::from_str(stderr.trim()).unwrap();
+            let first = fs::read_to_string(output.join(format!("{}_part_001.txt", name))).unwrap();
+            (first, summary["parts"].clone(), summary["total_tokens"].clone())
+        };
+        let composed = split("composed", "Caf\u{e9} r\u{e9}sum\u{e9} na\u{ef}ve");
+        let decomposed = split("decomposed", "Cafe\u{301} re\u{301}sume\u{301} nai\u{308}ve");
+        assert_eq!(decomposed.0, "Caf\u{e9} r\u{e9}sum\u{e9}\n\n");
+        assert_eq!(decomposed, composed);
+    }
+
+    #[test]
+    fn respect_markdown_never_splits_a_code_fence() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two three\n```rust\nlet a = 1;\nlet b = 2;\n```\nfour five six seven").unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "5",
+            "--respect-markdown",
+            "--no-auto-config",
+        ])
+        .unwrap();
+        let mut parts: Vec<String> = fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        parts.sort();
+        // The fence is longer than the budget, so it becomes a part of its own instead of being cut
+        assert!(parts.iter().all(|part| part.matches("```").count() % 2 == 0));
+        assert!(parts.contains(&"```rust\nlet a = 1;\nlet b = 2;\n```\n\n".to_string()));
+        assert!(parts.contains(&"one two three\n\n".to_string()));
+    }
+
+    #[test]
+    fn budget_of_a_context_window_sets_the_tokens_per_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        let words: Vec<String> = (0..4100).map(|i| format!("w{}", i)).collect();
+        fs::write(&input, words.join(" ")).unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--budget",
+            "50%",
+            "--context-window",
+            "4096",
+            "--no-auto-config",
+        ])
+        .unwrap();
+        // 50% of 4096 is 2048 tokens per split, so 4100 tokens make three parts
+        let tokens = |index: usize| {
+            fs::read_to_string(output.join(format!("talk_part_{:03}.txt", index))).unwrap().split_whitespace().count()
+        };
+        assert_eq!(fs::read_dir(&output).unwrap().count(), 3);
+        assert_eq!([tokens(1), tokens(2), tokens(3)], [2048, 2048, 4]);
+    }
+
+    #[test]
+    fn replace_substitutes_before_the_text_is_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "Jon Smyth said hi. Later Jon Smyth left.").unwrap();
+        let output = dir.path().join("splits");
+        run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "4",
+            "--replace",
+            r"Jon\s+Smyth=John Smith",
+            "--replace",
+            "hi=hello",
+            "--no-auto-config",
+        ])
+        .unwrap();
+        let part = |index: usize| fs::read_to_string(output.join(format!("talk_part_{:03}.txt", index))).unwrap();
+        assert_eq!([part(1), part(2)], ["John Smith said hello.\n\n", "Later John Smith left.\n\n"]);
+
+        let invalid = dir.path().join("invalid");
+        let error = run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            invalid.to_str().unwrap(),
+            "--replace",
+            "Jon(=John",
+            "--no-auto-config",
+        ])
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().starts_with("Invalid --replace regex 'Jon('"), "{}", error);
+        assert!(!invalid.exists());
+    }
+
+    #[test]
+    fn malformed_config_stops_the_run_before_any_part_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("talk.txt");
+        fs::write(&input, "one two three").unwrap();
+        let config = dir.path().join("settings.json");
+        fs::write(&config, "{\"header\": \"Summarize:\",}").unwrap();
+        let output = dir.path().join("splits");
+        let result = run_splitter(&[
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "-s",
+            "2",
+            "-c",
+            config.to_str().unwrap(),
+        ]);
+        assert!(!output.exists());
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.starts_with("Invalid configuration JSON in"), "{}", error);
+        assert!(error.contains("trailing comma at line 1 column 25"), "{}", error);
+    }
+}